@@ -1,8 +1,7 @@
 mod recorder;
 
 use crate::recorder::Recorder;
-use bing_stt::speech_recognition::build_wave_header_from_wave_format;
-use bing_stt::{Session, VoiceActivityDetector};
+use bing_stt::{AudioFormat, RecognitionEvent, RecognitionStatus, SampleType, Session, SessionBuilder, VoiceActivityDetector};
 use std::slice;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
@@ -15,21 +14,31 @@ fn main() -> anyhow::Result<()> {
 
     let wave_format = recorder.wave_format();
     let channels = wave_format.as_ref().nChannels as usize;
-    let wave_header = build_wave_header_from_wave_format(&wave_format.as_bytes());
+    // 混音格式是 32 位浮点，由 Session 转换成 16 kHz 16 位单声道
+    let audio_format = AudioFormat::new(wave_format.as_ref().nSamplesPerSec, channels as u16, SampleType::F32);
 
     let mut speech_recognition_session: Option<Session> = None;
     let mut stop_time = Instant::now();
     loop {
+        // 有会话时等待识别结果，没有会话时只是等待下一段录音
         if let Some(session) = &mut speech_recognition_session {
-            if let Some((text, finished)) = session.try_recv_message()? {
-                if finished {
-                    println!("------> {}", text);
+            match session.recv_message_timeout(Duration::from_millis(10))? {
+                Some(RecognitionEvent::Phrase(phrase)) => {
+                    match &phrase.recognition_status {
+                        RecognitionStatus::Success => println!("------> {}", phrase.display_text),
+                        RecognitionStatus::NoMatch | RecognitionStatus::InitialSilenceTimeout | RecognitionStatus::BabbleTimeout => println!("------> (no speech recognized)"),
+                        status => eprintln!("Recognition failed: {}", status.as_str()),
+                    }
                     stop_time = Instant::now();
                     speech_recognition_session = None;
-                } else {
-                    println!("{} ...", text);
                 }
+                Some(RecognitionEvent::Hypothesis(hypothesis)) => {
+                    println!("{} ...", hypothesis.text);
+                }
+                _ => {}
             }
+        } else {
+            sleep(Duration::from_millis(10));
         }
 
         recorder.capture(|captured_buffer| {
@@ -39,18 +48,10 @@ fn main() -> anyhow::Result<()> {
             if is_active || now < stop_time {
                 stop_time = now + Duration::from_secs(3);
                 if speech_recognition_session.is_none() {
-                    match Session::new("zh-CN") {
-                        Ok(mut session) => {
+                    match SessionBuilder::new("zh-CN").audio_format(audio_format).connect() {
+                        Ok(session) => {
                             println!("======> Session created");
-                            match session.write(&wave_header) {
-                                Ok(_) => {
-                                    speech_recognition_session = Some(session);
-                                }
-                                Err(e) => {
-                                    eprintln!("Failed to write wave header: {}", e);
-                                    speech_recognition_session = None;
-                                }
-                            }
+                            speech_recognition_session = Some(session);
                         }
                         Err(e) => {
                             eprintln!("Failed to create session: {}", e);
@@ -68,6 +69,5 @@ fn main() -> anyhow::Result<()> {
                 }
             }
         })?;
-        sleep(Duration::from_millis(10));
     }
 }
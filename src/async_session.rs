@@ -0,0 +1,250 @@
+//! 基于 tokio 的异步会话。音频通过 [`Sink`] 或 [`AsyncWrite`] 写入，识别结果通过 [`Stream`] 读取。
+//!
+//! 需要在不同的任务中同时写入音频和读取结果时，可以使用 [`futures_util::StreamExt::split`]。
+
+use crate::error::{Error, Result};
+use crate::session_builder::SessionBuilder;
+use crate::speech_recognition::{close_error, RecognitionEvent, TurnState};
+use futures_util::future::poll_fn;
+use futures_util::{ready, Sink, Stream};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use websocket::WebSocketError;
+
+pub(crate) fn ws_error(e: tungstenite::Error) -> Error {
+    match e {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => Error::Closed,
+        e => Error::WebSocket(WebSocketError::Other(Box::new(e))),
+    }
+}
+
+pub(crate) fn connect_error(e: tungstenite::Error) -> Error {
+    match e {
+        tungstenite::Error::Io(e) => Error::Connect(e),
+        e => Error::Handshake(Box::new(e)),
+    }
+}
+
+pub struct AsyncSession {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    turn: TurnState,
+    /// 新的一轮开始时，需要在音频之前发送的 speech.context 消息
+    pending_context: Option<String>,
+    turn_ended: bool,
+    closed: bool,
+}
+
+impl AsyncSession {
+    /// Connects with the default settings, use [`SessionBuilder::connect_async`] for more options.
+    ///
+    /// # Arguments
+    /// * `default_language` - "zh-CN", "en-US"
+    /// # Returns
+    /// * Same as [`Session::new`](crate::Session::new)
+    pub async fn new(default_language: &str) -> Result<Self> {
+        SessionBuilder::new(default_language).connect_async().await
+    }
+
+    /// 已经发送完 speech.config 和 speech.context 之后，构造会话
+    pub(crate) fn from_stream(stream: WebSocketStream<MaybeTlsStream<TcpStream>>, builder: &SessionBuilder, request_id: String) -> Self {
+        Self {
+            stream,
+            turn: TurnState::new(builder, request_id),
+            pending_context: None,
+            turn_ended: false,
+            closed: false,
+        }
+    }
+
+    /// 当前这一轮的 X-RequestId
+    pub fn request_id(&self) -> &str {
+        self.turn.request_id()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// See [`Session::audio_bytes_before_turn`](crate::Session::audio_bytes_before_turn)
+    pub fn audio_bytes_before_turn(&self) -> u64 {
+        self.turn.audio_bytes_before_turn()
+    }
+
+    /// 发送剩余的音频，然后发送一个空的 audio 消息，告诉服务端音频已经结束。
+    /// 之后不能再写入音频，[`Stream`] 返回最后的结果和 turn.end 之后结束。连续识别时，如果服务端提前结束了这一轮，
+    /// 没有识别的音频会在新的一轮中重新发送，直到所有音频都识别完。
+    ///
+    /// # Returns
+    /// * Err(Error::Closed), when the session is already closed
+    /// * Err(Error::WebSocket), when sending audio fails
+    pub async fn end_audio(&mut self) -> Result<()> {
+        poll_fn(|cx| self.poll_end_audio(cx)).await
+    }
+
+    fn poll_end_audio(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.closed {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        if !self.turn.audio_ended() || self.turn.end_of_audio_pending() {
+            if self.turn.has_pending_audio() {
+                ready!(self.poll_send_buffer(cx))?;
+            }
+            ready!(self.poll_send_context(cx))?;
+            let message = self.turn.end_audio_message();
+            Pin::new(&mut self.stream).start_send(Message::binary(message)).map_err(ws_error)?;
+        }
+        Pin::new(&mut self.stream).poll_flush(cx).map_err(ws_error)
+    }
+
+    /// 新的一轮开始之后，在第一个 audio 消息之前发送 speech.context，并等待 WebSocket 可以继续发送
+    fn poll_send_context(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.pending_context.is_some() {
+            ready!(Pin::new(&mut self.stream).poll_ready(cx)).map_err(ws_error)?;
+            if let Some(context) = self.pending_context.take() {
+                Pin::new(&mut self.stream).start_send(Message::text(context)).map_err(ws_error)?;
+            }
+        }
+        ready!(Pin::new(&mut self.stream).poll_ready(cx)).map_err(ws_error)?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// 把缓冲区作为一个 audio 消息交给 WebSocket，但不保证已经发送出去
+    fn poll_send_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_send_context(cx))?;
+        if let Some(message) = self.turn.take_audio_message() {
+            Pin::new(&mut self.stream).start_send(Message::binary(message)).map_err(ws_error)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSession {
+    fn poll_ready_audio(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.closed {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        if self.turn.is_full() {
+            ready!(self.poll_send_buffer(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn push_audio(&mut self, data: &[u8]) -> Result<()> {
+        if self.closed || self.turn.audio_ended() {
+            return Err(Error::Closed);
+        }
+        self.turn.push_audio(data);
+        Ok(())
+    }
+
+    fn poll_flush_audio(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.closed {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        if self.turn.has_pending_audio() {
+            ready!(self.poll_send_buffer(cx))?;
+        }
+        Pin::new(&mut self.stream).poll_flush(cx).map_err(ws_error)
+    }
+
+    fn poll_close_audio(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if !self.closed {
+            if self.turn.has_pending_audio() {
+                ready!(self.poll_send_buffer(cx))?;
+            }
+            self.closed = true;
+        }
+        Pin::new(&mut self.stream).poll_close(cx).map_err(ws_error)
+    }
+}
+
+/// 每一项是一段音频。只借用数据时可以使用 [`AsyncWrite`]
+impl Sink<Vec<u8>> for AsyncSession {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_ready_audio(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<()> {
+        self.get_mut().push_audio(&item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_flush_audio(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_close_audio(cx)
+    }
+}
+
+impl AsyncWrite for AsyncSession {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_ready_audio(cx)).map_err(io::Error::other)?;
+        this.push_audio(buf).map_err(io::Error::other)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_audio(cx).map_err(io::Error::other)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_close_audio(cx).map_err(io::Error::other)
+    }
+}
+
+impl Stream for AsyncSession {
+    type Item = Result<RecognitionEvent>;
+
+    /// 收到 turn.end 之后结束。连续识别时直到调用 [`AsyncSession::end_audio`] 之后的 turn.end，或者连接关闭才结束
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.turn_ended {
+            return Poll::Ready(None);
+        }
+        if this.turn.end_of_audio_pending() {
+            // 重新发送上一轮没有识别的音频，然后再次结束音频
+            if let Err(e) = ready!(this.poll_end_audio(cx)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+        loop {
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                None => {
+                    this.closed = true;
+                    return Poll::Ready(None);
+                }
+                Some(Err(e)) => return Poll::Ready(Some(Err(ws_error(e)))),
+                Some(Ok(Message::Text(text))) => {
+                    let event = this.turn.parse(&text);
+                    if let Ok(RecognitionEvent::TurnEnd) = event {
+                        match this.turn.end_turn() {
+                            Some(context) => this.pending_context = Some(context),
+                            None => {
+                                this.turn_ended = true;
+                                this.closed = true;
+                            }
+                        }
+                    }
+                    return Poll::Ready(Some(event));
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    this.closed = true;
+                    return match frame.map(|f| close_error(f.code.into(), f.reason.into_owned())) {
+                        Some(Error::Closed) | None => Poll::Ready(None),
+                        Some(e) => Poll::Ready(Some(Err(e))),
+                    };
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
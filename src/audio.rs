@@ -0,0 +1,220 @@
+//! 把任意采样率、声道数和采样格式的 PCM 音频转换成服务端需要的 16 kHz 16 位单声道。
+//!
+//! 多声道取平均值混合成单声道；降采样时对每个输出采样覆盖的输入求平均，升采样时线性插值。
+
+/// 发送给服务端的音频编码，决定每一轮第一个 audio 消息的 Content-Type
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub enum AudioEncoding {
+    /// WAV 头部加 PCM 数据
+    #[default]
+    Wav,
+    /// OGG 容器中的 Opus
+    OggOpus,
+    /// WebM 容器中的 Opus，例如浏览器的 MediaRecorder 录制的音频
+    WebmOpus,
+    Mp3,
+    /// 其他服务端支持的 Content-Type
+    Other(String),
+}
+
+impl AudioEncoding {
+    pub fn content_type(&self) -> &str {
+        match self {
+            AudioEncoding::Wav => "audio/x-wav",
+            AudioEncoding::OggOpus => "audio/ogg; codecs=opus",
+            AudioEncoding::WebmOpus => "audio/webm; codecs=opus",
+            AudioEncoding::Mp3 => "audio/mpeg",
+            AudioEncoding::Other(content_type) => content_type,
+        }
+    }
+
+    /// 压缩格式的数据原样发送，不会添加 WAV 头部，也不会做格式转换
+    pub fn is_compressed(&self) -> bool {
+        *self != AudioEncoding::Wav
+    }
+}
+
+/// 每个采样的格式，都是小端序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    I16,
+    /// 3 字节有符号整数
+    I24,
+    I32,
+    /// IEEE float，范围 [-1.0, 1.0]
+    F32,
+}
+
+impl SampleType {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            SampleType::I16 => 2,
+            SampleType::I24 => 3,
+            SampleType::I32 | SampleType::F32 => 4,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleType::I24 => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8388608.0,
+            SampleType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0,
+            SampleType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// 交错排列（interleaved）的 PCM 音频格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_type: SampleType,
+}
+
+impl AudioFormat {
+    /// 实际发送给服务端的格式
+    pub const TARGET: AudioFormat = AudioFormat {
+        sample_rate: 16000,
+        channels: 1,
+        sample_type: SampleType::I16,
+    };
+
+    pub fn new(sample_rate: u32, channels: u16, sample_type: SampleType) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            sample_type,
+        }
+    }
+
+    /// 每一帧（所有声道各一个采样）的字节数
+    pub fn block_align(&self) -> usize {
+        self.channels as usize * self.sample_type.bytes_per_sample()
+    }
+}
+
+/// 流式的格式转换器，每次写入的数据不需要按帧对齐，不完整的帧留到下一次
+#[derive(Debug, Clone)]
+pub struct AudioConverter {
+    format: AudioFormat,
+    /// 上次剩下的不完整的帧
+    remainder: Vec<u8>,
+    /// 已经混合成单声道，但还没有被重采样用掉的采样
+    pending: Vec<f32>,
+    /// 下一个输出采样在 pending 中的位置
+    position: f64,
+}
+
+impl AudioConverter {
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            remainder: Vec::new(),
+            pending: Vec::new(),
+            position: 0.0,
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// 输入 `format` 格式的音频，返回 [`AudioFormat::TARGET`] 格式的音频
+    pub fn convert(&mut self, data: &[u8]) -> Vec<u8> {
+        let block_align = self.format.block_align();
+        if block_align == 0 || self.format.sample_rate == 0 {
+            return Vec::new();
+        }
+        self.remainder.extend_from_slice(data);
+        let complete = self.remainder.len() / block_align * block_align;
+        if self.format == AudioFormat::TARGET {
+            return self.remainder.drain(..complete).collect();
+        }
+        let bytes_per_sample = self.format.sample_type.bytes_per_sample();
+        for frame in self.remainder[..complete].chunks_exact(block_align) {
+            let sum: f32 = frame.chunks_exact(bytes_per_sample).map(|sample| self.format.sample_type.decode(sample)).sum();
+            self.pending.push(sum / self.format.channels as f32);
+        }
+        self.remainder.drain(..complete);
+        self.resample()
+    }
+
+    fn resample(&mut self) -> Vec<u8> {
+        let step = self.format.sample_rate as f64 / AudioFormat::TARGET.sample_rate as f64;
+        let mut output = Vec::with_capacity((self.pending.len() as f64 / step) as usize * 2 + 2);
+        loop {
+            let sample = if step > 1.0 {
+                let start = self.position as usize;
+                let end = ((self.position + step) as usize).max(start + 1);
+                if end > self.pending.len() {
+                    break;
+                }
+                self.pending[start..end].iter().sum::<f32>() / (end - start) as f32
+            } else {
+                let index = self.position as usize;
+                if index + 1 >= self.pending.len() {
+                    break;
+                }
+                let fraction = (self.position - index as f64) as f32;
+                self.pending[index] * (1.0 - fraction) + self.pending[index + 1] * fraction
+            };
+            output.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+            self.position += step;
+        }
+        let consumed = (self.position as usize).min(self.pending.len());
+        self.pending.drain(..consumed);
+        self.position -= consumed as f64;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i16_samples(data: &[u8]) -> Vec<i16> {
+        data.chunks_exact(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect()
+    }
+
+    #[test]
+    fn target_format_passes_through() {
+        let mut converter = AudioConverter::new(AudioFormat::TARGET);
+        assert_eq!(converter.convert(&[1, 2, 3]), [1, 2]);
+        assert_eq!(converter.convert(&[4]), [3, 4]);
+    }
+
+    #[test]
+    fn downmixes_and_downsamples_48k_stereo_f32() {
+        let mut converter = AudioConverter::new(AudioFormat::new(48000, 2, SampleType::F32));
+        // 左声道 0.5，右声道 0.0，混合之后是 0.25
+        let frame: Vec<u8> = [0.5f32, 0.0].iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        let input: Vec<u8> = frame.iter().copied().cycle().take(frame.len() * 48000).collect();
+        let mut output = Vec::new();
+        // 每次写入的数据不按帧对齐
+        for chunk in input.chunks(1001) {
+            output.extend(converter.convert(chunk));
+        }
+        let samples = i16_samples(&output);
+        assert_eq!(samples.len(), 16000);
+        assert!(samples.iter().all(|&sample| sample == (0.25 * 32767.0) as i16));
+    }
+
+    #[test]
+    fn resamples_44k_i16() {
+        let mut converter = AudioConverter::new(AudioFormat::new(44100, 1, SampleType::I16));
+        let input: Vec<u8> = (0..44100).flat_map(|_| 1000i16.to_le_bytes()).collect();
+        let samples = i16_samples(&converter.convert(&input));
+        assert!((15990..=16000).contains(&samples.len()), "{} samples", samples.len());
+        assert!(samples.iter().all(|&sample| (sample - 1000).abs() <= 1));
+    }
+
+    #[test]
+    fn upsamples_8k_i24() {
+        let mut converter = AudioConverter::new(AudioFormat::new(8000, 1, SampleType::I24));
+        let input: Vec<u8> = (0..800).flat_map(|_| [0x00, 0x00, 0x40]).collect();
+        let samples = i16_samples(&converter.convert(&input));
+        assert!((1590..=1600).contains(&samples.len()), "{} samples", samples.len());
+        assert!(samples.iter().all(|&sample| (sample - 16383).abs() <= 1));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::{Error, Result};
+use crate::speech_recognition::{RecognitionEvent, Session};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// 后台线程等待识别结果的最长时间，之后处理一次待发送的音频
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+enum Command {
+    Audio(Vec<u8>),
+    Flush,
+}
+
+/// 在后台线程中运行的 [`Session`]。音频通过 channel 交给后台线程发送，识别结果也通过 channel 返回，
+/// 调用方等待结果时不会占用 CPU。会话关闭，或者结果无人接收时，后台线程会关闭连接并退出。
+pub struct BackgroundSession {
+    sender: AudioSender,
+    receiver: ResultReceiver,
+}
+
+impl BackgroundSession {
+    pub(crate) fn new(session: Session) -> Self {
+        let (commands, command_receiver) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+        thread::spawn(move || run(session, command_receiver, event_sender));
+        Self {
+            sender: AudioSender { commands },
+            receiver: ResultReceiver { events },
+        }
+    }
+
+    /// 拆分成发送音频和接收结果的两半，可以分别放在不同的线程中
+    pub fn split(self) -> (AudioSender, ResultReceiver) {
+        (self.sender, self.receiver)
+    }
+
+    /// See [`AudioSender::write`]
+    pub fn write(&self, data: impl AsRef<[u8]>) -> Result<()> {
+        self.sender.write(data)
+    }
+
+    /// See [`AudioSender::flush`]
+    pub fn flush(&self) -> Result<()> {
+        self.sender.flush()
+    }
+
+    /// See [`ResultReceiver::recv`]
+    pub fn recv(&self) -> Result<RecognitionEvent> {
+        self.receiver.recv()
+    }
+
+    /// See [`ResultReceiver::recv_timeout`]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<RecognitionEvent>> {
+        self.receiver.recv_timeout(timeout)
+    }
+
+    /// See [`ResultReceiver::try_recv`]
+    pub fn try_recv(&self) -> Result<Option<RecognitionEvent>> {
+        self.receiver.try_recv()
+    }
+}
+
+/// 发送音频的一半，可以 clone 给多个线程。所有的 AudioSender 都释放之后，剩余的音频会被发送出去，
+/// 并且通过 [`Session::end_audio`] 告诉服务端音频已经结束，后台线程继续接收结果直到会话关闭。
+#[derive(Clone)]
+pub struct AudioSender {
+    commands: Sender<Command>,
+}
+
+impl AudioSender {
+    /// # Returns
+    /// * Err(Error::Closed), when the background thread has stopped
+    pub fn write(&self, data: impl AsRef<[u8]>) -> Result<()> {
+        self.commands.send(Command::Audio(data.as_ref().to_vec())).map_err(|_| Error::Closed)
+    }
+
+    /// # Returns
+    /// * Err(Error::Closed), when the background thread has stopped
+    pub fn flush(&self) -> Result<()> {
+        self.commands.send(Command::Flush).map_err(|_| Error::Closed)
+    }
+}
+
+/// 接收识别结果的一半
+pub struct ResultReceiver {
+    events: Receiver<Result<RecognitionEvent>>,
+}
+
+impl ResultReceiver {
+    /// # Returns
+    /// * Err(Error::Closed), when the session is closed and all events have been received
+    /// * Err(e), errors from [`Session::recv_message`], after which the session is closed
+    pub fn recv(&self) -> Result<RecognitionEvent> {
+        self.events.recv().map_err(|_| Error::Closed)?
+    }
+
+    /// # Returns
+    /// * Same as [`ResultReceiver::recv`], Ok(None) when the timeout elapses
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<RecognitionEvent>> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => event.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Closed),
+        }
+    }
+
+    /// # Returns
+    /// * Same as [`ResultReceiver::recv`], Ok(None) when no event is available
+    pub fn try_recv(&self) -> Result<Option<RecognitionEvent>> {
+        match self.events.try_recv() {
+            Ok(event) => event.map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(Error::Closed),
+        }
+    }
+}
+
+impl Iterator for ResultReceiver {
+    type Item = Result<RecognitionEvent>;
+
+    /// 会话关闭之后结束
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.recv().ok()
+    }
+}
+
+fn run(mut session: Session, commands: Receiver<Command>, events: Sender<Result<RecognitionEvent>>) {
+    let mut audio_finished = false;
+    loop {
+        while !audio_finished {
+            let result = match commands.try_recv() {
+                Ok(Command::Audio(data)) => session.write(data),
+                Ok(Command::Flush) => session.flush(),
+                Err(TryRecvError::Empty) => break,
+                // 不会再有音频了，发送剩余的音频，然后继续接收结果
+                Err(TryRecvError::Disconnected) => {
+                    audio_finished = true;
+                    session.end_audio()
+                }
+            };
+            if let Err(e) = result {
+                let _ = events.send(Err(e));
+                return;
+            }
+        }
+        match session.recv_message_timeout(POLL_INTERVAL) {
+            Ok(Some(event)) => {
+                if events.send(Ok(event)).is_err() || session.is_closed() {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                let _ = events.send(Err(e));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! 命令行语音识别。从文件或标准输入读取 WAV 或 PCM 音频，中间结果输出到 stderr，最终结果每句一行输出到 stdout。
+//!
+//! ```bash
+//! bing-stt -l zh-CN meeting.wav > meeting.txt
+//! arecord -f S16_LE -r 16000 -c 1 -t raw | bing-stt --raw -l en-US
+//! ```
+
+use bing_stt::{transcribe_stream, wav_stream, AudioFormat, Authentication, Endpoint, JsonlWriter, OutputFormat, RecognitionMode, SampleType, SessionBuilder, TranscribeOptions, TranscriptEvent};
+use clap::{ArgGroup, Parser, ValueEnum};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Simple,
+    Detailed,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ModeArg {
+    Interactive,
+    Conversation,
+    Dictation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputArg {
+    /// 每句一行文本
+    Text,
+    /// 识别结束后输出完整的 JSON
+    Json,
+    /// 每个事件一行 JSON
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SampleTypeArg {
+    I16,
+    I24,
+    I32,
+    F32,
+}
+
+#[derive(Debug, Parser)]
+#[command(name = "bing-stt", version, about = "Speech to text from a WAV or raw PCM file, or from stdin")]
+#[command(group = ArgGroup::new("service").args(["endpoint", "region"]))]
+struct Args {
+    /// 输入文件，不指定或者为 "-" 时从标准输入读取
+    input: Option<PathBuf>,
+
+    /// 识别的语言
+    #[arg(short, long, default_value = "zh-CN")]
+    language: String,
+
+    /// 自定义服务地址，支持 ws:// 和 wss://，默认使用 bing.com
+    #[arg(long, conflicts_with = "region")]
+    endpoint: Option<String>,
+
+    /// Azure 语音服务的区域，例如 eastus，需要配合 --key 使用
+    #[arg(long, requires = "key")]
+    region: Option<String>,
+
+    /// Azure 语音服务的密钥，需要配合 --region 或 --endpoint 使用
+    #[arg(long, requires = "service")]
+    key: Option<String>,
+
+    #[arg(long, value_enum, default_value = "simple")]
+    format: FormatArg,
+
+    /// interactive 会被当作 conversation，以便识别多句话
+    #[arg(long, value_enum, default_value = "conversation")]
+    mode: ModeArg,
+
+    /// stdout 的输出格式，中间结果总是输出到 stderr
+    #[arg(short, long, value_enum, default_value = "text")]
+    output: OutputArg,
+
+    /// 输入是没有 WAV 头部的 PCM 音频
+    #[arg(long)]
+    raw: bool,
+
+    /// PCM 音频的采样率，只在 --raw 时使用
+    #[arg(long, default_value_t = 16000)]
+    sample_rate: u32,
+
+    /// PCM 音频的声道数，只在 --raw 时使用
+    #[arg(long, default_value_t = 1)]
+    channels: u16,
+
+    /// PCM 音频的采样格式，只在 --raw 时使用
+    #[arg(long, value_enum, default_value = "i16")]
+    sample_type: SampleTypeArg,
+
+    /// 发送速度，1 是实时，0 不限速
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// 音频发送完之后等待下一个结果的秒数，超时退出
+    #[arg(long, default_value_t = 30)]
+    end_timeout: u64,
+}
+
+impl Args {
+    fn session_builder(&self) -> SessionBuilder {
+        let mut builder = SessionBuilder::new(&self.language)
+            .output_format(match self.format {
+                FormatArg::Simple => OutputFormat::Simple,
+                FormatArg::Detailed => OutputFormat::Detailed,
+            })
+            .recognition_mode(match self.mode {
+                ModeArg::Interactive => RecognitionMode::Interactive,
+                ModeArg::Conversation => RecognitionMode::Conversation,
+                ModeArg::Dictation => RecognitionMode::Dictation,
+            });
+        let authentication = self.key.clone().map(Authentication::SubscriptionKey);
+        if let (Some(region), Some(authentication)) = (&self.region, &authentication) {
+            builder = builder.endpoint(Endpoint::azure(region, authentication.clone()));
+        } else if let Some(url) = &self.endpoint {
+            builder = builder.endpoint(Endpoint::Custom {
+                url: url.clone(),
+                authentication,
+            });
+        }
+        builder
+    }
+
+    fn input(&self) -> io::Result<Box<dyn Read>> {
+        match &self.input {
+            Some(path) if path.as_os_str() != "-" => Ok(Box::new(BufReader::new(File::open(path)?))),
+            _ => Ok(Box::new(io::stdin().lock())),
+        }
+    }
+}
+
+fn print_event(event: &TranscriptEvent, print_phrase: bool) {
+    match event {
+        TranscriptEvent::Hypothesis { text, .. } => eprintln!("{} ...", text),
+        TranscriptEvent::Phrase(phrase) if print_phrase => {
+            let mut stdout = io::stdout().lock();
+            let _ = writeln!(stdout, "{}", phrase.text);
+            let _ = stdout.flush();
+        }
+        _ => {}
+    }
+}
+
+fn run(args: &Args) -> bing_stt::Result<()> {
+    let options = TranscribeOptions::from(args.session_builder()).speed(args.speed).end_of_stream_timeout(Duration::from_secs(args.end_timeout));
+    let input = args.input().map_err(bing_stt::Error::Io)?;
+    let (audio_format, input): (AudioFormat, Box<dyn Read>) = if args.raw {
+        let sample_type = match args.sample_type {
+            SampleTypeArg::I16 => SampleType::I16,
+            SampleTypeArg::I24 => SampleType::I24,
+            SampleTypeArg::I32 => SampleType::I32,
+            SampleTypeArg::F32 => SampleType::F32,
+        };
+        (AudioFormat::new(args.sample_rate, args.channels, sample_type), input)
+    } else {
+        let (audio_format, data) = wav_stream(input)?;
+        (audio_format, Box::new(data))
+    };
+    match args.output {
+        OutputArg::Text => {
+            transcribe_stream(input, audio_format, &options, |event| print_event(event, true))?;
+        }
+        OutputArg::Json => {
+            let transcript = transcribe_stream(input, audio_format, &options, |event| print_event(event, false))?;
+            let mut stdout = io::stdout().lock();
+            serde_json::to_writer_pretty(&mut stdout, &transcript).map_err(|e| bing_stt::Error::Io(e.into()))?;
+            writeln!(stdout).map_err(bing_stt::Error::Io)?;
+        }
+        OutputArg::Jsonl => {
+            let mut writer = JsonlWriter::new(io::stdout().lock());
+            let mut result = Ok(());
+            transcribe_stream(input, audio_format, &options, |event| {
+                if let TranscriptEvent::Hypothesis { .. } = event {
+                    print_event(event, false);
+                } else if result.is_ok() {
+                    result = writer.write(event);
+                }
+            })?;
+            result.map_err(bing_stt::Error::Io)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("bing-stt: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
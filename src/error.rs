@@ -0,0 +1,132 @@
+use crate::speech_recognition::RecognitionStatus;
+use std::fmt;
+use websocket::url::ParseError;
+use websocket::WebSocketError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// 服务端以错误码关闭连接的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancellationReason {
+    /// 请求或音频格式错误，或者不支持的语言，重试也不会成功。关闭码 1002、1003、1007
+    BadRequest,
+    /// 认证失败或者没有权限。关闭码 1008
+    Forbidden,
+    /// 超过了配额或者并发限制，稍后重试。关闭码 4429
+    TooManyRequests,
+    /// 服务端内部错误。关闭码 1011
+    ServiceError,
+    /// 服务暂时不可用，例如服务端正在重启。关闭码 1001、1012、1013
+    ServiceUnavailable,
+    /// 连接异常断开，或者其他未知的关闭码
+    ConnectionFailure,
+}
+
+impl CancellationReason {
+    pub fn from_close_code(code: u16) -> Self {
+        match code {
+            1002 | 1003 | 1007 => CancellationReason::BadRequest,
+            1008 => CancellationReason::Forbidden,
+            4429 => CancellationReason::TooManyRequests,
+            1011 => CancellationReason::ServiceError,
+            1001 | 1012 | 1013 => CancellationReason::ServiceUnavailable,
+            _ => CancellationReason::ConnectionFailure,
+        }
+    }
+
+    /// 稍后重试可能会成功
+    pub fn is_transient(&self) -> bool {
+        matches!(self, CancellationReason::TooManyRequests | CancellationReason::ServiceUnavailable | CancellationReason::ConnectionFailure)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// 请求 URL 无法解析
+    InvalidUrl(ParseError),
+    /// TCP 连接失败，通常可以重试
+    Connect(std::io::Error),
+    /// TLS 或 WebSocket 握手失败
+    Handshake(Box<dyn std::error::Error + Send + Sync>),
+    /// WebSocket 传输或协议错误
+    WebSocket(WebSocketError),
+    /// 服务端消息无法解析
+    Parse {
+        path: String,
+        source: serde_json::Error,
+    },
+    /// 服务端以非正常的关闭码关闭了连接
+    Service {
+        code: u16,
+        reason: String,
+        cancellation: CancellationReason,
+    },
+    /// speech.phrase 的 RecognitionStatus 是 Error 或者未知的状态，例如请求被服务端拒绝
+    Recognition { status: RecognitionStatus },
+    /// 会话已经关闭，不能再收发消息
+    Closed,
+    /// 等待服务端的结果超时
+    Timeout,
+    /// WAV 文件格式错误或不支持
+    InvalidWav(String),
+    /// 读取音频失败
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(e) => write!(f, "invalid request url: {}", e),
+            Error::Connect(e) => write!(f, "failed to connect: {}", e),
+            Error::Handshake(e) => write!(f, "handshake failed: {}", e),
+            Error::WebSocket(e) => write!(f, "websocket error: {}", e),
+            Error::Parse { path, source } => write!(f, "failed to parse {} message: {}", path, source),
+            Error::Service { code, reason, cancellation } => write!(f, "service closed the connection ({}, {:?}): {}", code, cancellation, reason),
+            Error::Recognition { status } => write!(f, "recognition failed with status {}", status.as_str()),
+            Error::Closed => write!(f, "session is closed"),
+            Error::Timeout => write!(f, "timed out waiting for the service"),
+            Error::InvalidWav(reason) => write!(f, "invalid wav: {}", reason),
+            Error::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl Error {
+    /// 服务端关闭连接的原因，其他错误返回 None
+    pub fn cancellation_reason(&self) -> Option<CancellationReason> {
+        match self {
+            Error::Service { cancellation, .. } => Some(*cancellation),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidUrl(e) => Some(e),
+            Error::Connect(e) => Some(e),
+            Error::Handshake(e) => Some(e.as_ref()),
+            Error::WebSocket(e) => Some(e),
+            Error::Parse { source, .. } => Some(source),
+            Error::Service { .. } => None,
+            Error::Recognition { .. } => None,
+            Error::Closed => None,
+            Error::Timeout => None,
+            Error::InvalidWav(_) => None,
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::InvalidUrl(e)
+    }
+}
+
+impl From<WebSocketError> for Error {
+    fn from(e: WebSocketError) -> Self {
+        Error::WebSocket(e)
+    }
+}
@@ -1,5 +1,29 @@
+#[cfg(feature = "tokio")]
+pub mod async_session;
+pub mod audio;
+pub mod background_session;
+pub mod error;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod profanity;
+pub mod resilient_session;
+pub mod session_builder;
 pub mod speech_recognition;
+pub mod subtitles;
+pub mod transcribe;
 pub mod voice_activity_detection;
+pub mod wav;
 
-pub use speech_recognition::Session;
+#[cfg(feature = "tokio")]
+pub use async_session::AsyncSession;
+pub use audio::{AudioConverter, AudioEncoding, AudioFormat, SampleType};
+pub use background_session::{AudioSender, BackgroundSession, ResultReceiver};
+pub use error::{CancellationReason, Error, Result};
+pub use profanity::{Profanity, ProfanityFilter};
+pub use resilient_session::{ReconnectPolicy, ResilientSession};
+pub use session_builder::{AudioSource, Authentication, Endpoint, Punctuation, SessionBuilder};
+pub use speech_recognition::{OutputFormat, RecognitionEvent, RecognitionMode, RecognitionStatus, Session};
+pub use subtitles::{Cue, SubtitleOptions, SubtitlePhrase};
+pub use transcribe::{transcribe, transcribe_file, transcribe_stream, wav_stream, Alternate, JsonlWriter, TranscribeOptions, Transcript, TranscriptEvent, TranscriptPhrase, TranscriptWord, TRANSCRIPT_VERSION};
 pub use voice_activity_detection::VoiceActivityDetector;
+pub use wav::{WaveFormat, WaveFormatTag, WaveHeader};
@@ -0,0 +1,301 @@
+//! 本地模拟的语音识别服务，用于离线测试。
+//!
+//! 服务端使用和 bing.com 相同的 `Path:`/`X-RequestId` 文本消息和二进制 audio 消息格式，
+//! 检查 speech.config、speech.context、audio 的发送顺序，然后按脚本回放识别结果。
+//! 和真实的服务一样，发送 turn.end 之后忽略这一轮的 audio 消息，直到收到新的 speech.context。
+
+use crate::speech_recognition::{parse_headers, split_header_body};
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use websocket::sync::Server;
+use websocket::{CloseData, Message, OwnedMessage};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockAction {
+    /// 发送一条文本消息
+    Send { path: String, body: String },
+    /// 以指定的关闭码关闭连接
+    Close { code: u16, reason: String },
+    /// 不发送关闭帧，直接断开 TCP 连接，模拟网络故障
+    Disconnect,
+}
+
+/// 脚本中的一步：累计收到 `after_audio_bytes` 字节音频（不含消息头，不含被忽略的音频）之后执行 `action`。
+/// 收到表示音频结束的空 audio 消息之后，这一轮剩下的步骤，也就是直到下一个 turn.end 的步骤，都会立即执行。
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockStep {
+    pub after_audio_bytes: usize,
+    pub action: MockAction,
+}
+
+impl MockStep {
+    pub fn send(after_audio_bytes: usize, path: &str, body: &str) -> Self {
+        Self {
+            after_audio_bytes,
+            action: MockAction::Send {
+                path: path.to_owned(),
+                body: body.to_owned(),
+            },
+        }
+    }
+
+    pub fn close(after_audio_bytes: usize, code: u16, reason: &str) -> Self {
+        Self {
+            after_audio_bytes,
+            action: MockAction::Close {
+                code,
+                reason: reason.to_owned(),
+            },
+        }
+    }
+
+    pub fn disconnect(after_audio_bytes: usize) -> Self {
+        Self {
+            after_audio_bytes,
+            action: MockAction::Disconnect,
+        }
+    }
+
+    pub fn turn_start(after_audio_bytes: usize) -> Self {
+        Self::send(after_audio_bytes, "turn.start", r#"{"context":{"serviceTag":"mock"}}"#)
+    }
+
+    /// `offset` 和 `duration` 的单位是 100 纳秒
+    pub fn hypothesis(after_audio_bytes: usize, text: &str, offset: i64, duration: i64) -> Self {
+        let body = serde_json::json!({ "Text": text, "Offset": offset, "Duration": duration });
+        Self::send(after_audio_bytes, "speech.hypothesis", &body.to_string())
+    }
+
+    /// `offset` 和 `duration` 的单位是 100 纳秒
+    pub fn phrase(after_audio_bytes: usize, text: &str, offset: i64, duration: i64) -> Self {
+        let body = serde_json::json!({ "RecognitionStatus": "Success", "DisplayText": text, "Offset": offset, "Duration": duration });
+        Self::send(after_audio_bytes, "speech.phrase", &body.to_string())
+    }
+
+    pub fn turn_end(after_audio_bytes: usize) -> Self {
+        Self::send(after_audio_bytes, "turn.end", "{}")
+    }
+}
+
+/// 服务端收到的一条消息。audio 消息的 body 是去掉消息头之后的音频数据。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedMessage {
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockConnection {
+    /// 请求的路径和查询参数
+    pub uri: String,
+    pub messages: Vec<ReceivedMessage>,
+    /// 协议检查失败的原因，出现错误时服务端会以 1002 关闭连接
+    pub errors: Vec<String>,
+}
+
+fn header_value<'a>(headers: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+}
+
+/// 二进制 audio 消息：2 字节大端序的头部长度，头部文本，音频数据
+pub fn parse_audio_message(data: &[u8]) -> Option<ReceivedMessage> {
+    if data.len() < 2 {
+        return None;
+    }
+    let header_len = ((data[0] as usize) << 8) | data[1] as usize;
+    let header = data.get(2..2 + header_len)?;
+    let headers = parse_headers(std::str::from_utf8(header).ok()?);
+    Some(ReceivedMessage {
+        path: header_value(&headers, "Path").unwrap_or_default().to_owned(),
+        headers,
+        body: data[2 + header_len..].to_vec(),
+    })
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    connections: Arc<Mutex<Vec<MockConnection>>>,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// 在 127.0.0.1 的随机端口上启动服务，每个连接都从头回放一遍 `script`
+    pub fn start(script: Vec<MockStep>) -> io::Result<Self> {
+        let mut server = Server::bind("127.0.0.1:0")?;
+        let addr = server.local_addr()?;
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let connections = connections.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    let upgrade = match server.accept() {
+                        Ok(upgrade) => upgrade,
+                        Err(_) => continue,
+                    };
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let index = {
+                        let mut connections = connections.lock().unwrap();
+                        connections.push(MockConnection {
+                            uri: upgrade.uri(),
+                            ..Default::default()
+                        });
+                        connections.len() - 1
+                    };
+                    let client = match upgrade.accept() {
+                        Ok(client) => client,
+                        Err((_, e)) => {
+                            connections.lock().unwrap()[index].errors.push(format!("failed to accept: {}", e));
+                            continue;
+                        }
+                    };
+                    let connections = connections.clone();
+                    let script = script.clone();
+                    thread::spawn(move || serve(client, &script, &connections, index));
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            connections,
+            stopped,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 可以直接传给 [`SessionBuilder::endpoint`](crate::SessionBuilder::endpoint)
+    pub fn url(&self) -> String {
+        format!("ws://{}/speech/recognition/interactive/cognitiveservices/v1", self.addr)
+    }
+
+    /// 目前为止所有连接收到的消息
+    pub fn connections(&self) -> Vec<MockConnection> {
+        self.connections.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // 连接一次，让阻塞在 accept 上的线程醒过来
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(mut client: websocket::sync::Client<TcpStream>, script: &[MockStep], connections: &Mutex<Vec<MockConnection>>, index: usize) {
+    let record_error = |error: String| connections.lock().unwrap()[index].errors.push(error);
+    let mut request_id = String::new();
+    let mut last_path = String::new();
+    let mut audio_bytes = 0usize;
+    let mut next_step = 0usize;
+    // 已经发送了 turn.end，等待新的 speech.context
+    let mut turn_ended = false;
+    loop {
+        let message = match client.recv_message() {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        let received = match message {
+            OwnedMessage::Text(text) => {
+                let (header_text, body) = split_header_body(&text);
+                let headers = parse_headers(header_text);
+                ReceivedMessage {
+                    path: header_value(&headers, "Path").unwrap_or_default().to_owned(),
+                    headers,
+                    body: body.into_bytes(),
+                }
+            }
+            OwnedMessage::Binary(data) => match parse_audio_message(&data) {
+                Some(received) => received,
+                None => {
+                    record_error("malformed binary message".to_owned());
+                    let _ = client.send_message(&Message::close_because(1002, "malformed binary message"));
+                    return;
+                }
+            },
+            OwnedMessage::Close(_) => {
+                let _ = client.send_message(&Message::close());
+                return;
+            }
+            OwnedMessage::Ping(data) => {
+                let _ = client.send_message(&Message::pong(data));
+                continue;
+            }
+            OwnedMessage::Pong(_) => continue,
+        };
+        connections.lock().unwrap()[index].messages.push(received.clone());
+
+        let message_request_id = header_value(&received.headers, "X-RequestId").unwrap_or_default().to_owned();
+        let error = match received.path.as_str() {
+            _ if message_request_id.is_empty() => Some(format!("{} message without X-RequestId", received.path)),
+            "speech.config" if !last_path.is_empty() => Some("speech.config must be the first message".to_owned()),
+            "speech.config" => None,
+            "speech.context" if last_path.is_empty() => Some("speech.context before speech.config".to_owned()),
+            "speech.context" => {
+                request_id = message_request_id.clone();
+                turn_ended = false;
+                None
+            }
+            "audio" if last_path != "speech.context" && last_path != "audio" => Some("audio before speech.context".to_owned()),
+            "audio" if message_request_id != request_id => Some(format!("audio X-RequestId {} does not match speech.context {}", message_request_id, request_id)),
+            "audio" if turn_ended => None,
+            "audio" => {
+                audio_bytes += received.body.len();
+                None
+            }
+            path => Some(format!("unexpected path {:?}", path)),
+        };
+        if let Some(error) = error {
+            record_error(error.clone());
+            let _ = client.send_message(&Message::close_because(1002, error));
+            return;
+        }
+        last_path = received.path;
+
+        if last_path != "audio" || turn_ended {
+            continue;
+        }
+        let end_of_audio = received.body.is_empty();
+        while let Some(step) = script.get(next_step) {
+            if audio_bytes < step.after_audio_bytes && !end_of_audio {
+                break;
+            }
+            next_step += 1;
+            match &step.action {
+                MockAction::Send { path, body } => {
+                    let text = format!("X-RequestId:{}\r\nContent-Type:application/json; charset=utf-8\r\nPath:{}\r\n\r\n{}", request_id, path, body);
+                    if client.send_message(&Message::text(text)).is_err() {
+                        return;
+                    }
+                    if path == "turn.end" {
+                        turn_ended = true;
+                        break;
+                    }
+                }
+                MockAction::Close { code, reason } => {
+                    let _ = client.send_message(&OwnedMessage::Close(Some(CloseData::new(*code, reason.clone()))));
+                    return;
+                }
+                MockAction::Disconnect => {
+                    let _ = client.shutdown();
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! 脏话过滤。首选通过 [`SessionBuilder::profanity`](crate::SessionBuilder::profanity) 让服务端处理，
+//! 服务端忽略这个选项时，可以用 [`SessionBuilder::profanity_words`](crate::SessionBuilder::profanity_words) 在本地按词表过滤。
+
+use crate::speech_recognition::RecognitionEvent;
+
+/// 结果中脏话的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profanity {
+    /// 每个字符替换成 `*`，服务端的默认值
+    Masked,
+    /// 删除
+    Removed,
+    /// 原样保留
+    Raw,
+}
+
+impl Profanity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profanity::Masked => "masked",
+            Profanity::Removed => "removed",
+            Profanity::Raw => "raw",
+        }
+    }
+}
+
+/// 本地的脏话过滤器。英文等以字母数字组成的词按整词匹配，不区分 ASCII 大小写；中文等没有分隔符的词按子串匹配。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfanityFilter {
+    profanity: Profanity,
+    /// 按长度从长到短排列，优先匹配较长的词
+    words: Vec<String>,
+}
+
+impl ProfanityFilter {
+    pub fn new<S: AsRef<str>>(profanity: Profanity, words: impl IntoIterator<Item = S>) -> Self {
+        let mut words: Vec<String> = words.into_iter().map(|s| s.as_ref().trim().to_owned()).filter(|s| !s.is_empty()).collect();
+        words.sort();
+        words.dedup();
+        words.sort_by_key(|word| std::cmp::Reverse(word.len()));
+        Self { profanity, words }
+    }
+
+    /// 在 `start` 处匹配 `word`，返回匹配的结束位置
+    fn match_at(text: &str, start: usize, word: &str) -> Option<usize> {
+        let end = start + word.len();
+        if !text.get(start..end)?.eq_ignore_ascii_case(word) {
+            return None;
+        }
+        let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+        if is_word_char(word.chars().next()) && is_word_char(text[..start].chars().next_back()) {
+            return None;
+        }
+        if is_word_char(word.chars().next_back()) && is_word_char(text[end..].chars().next()) {
+            return None;
+        }
+        Some(end)
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        if self.profanity == Profanity::Raw || self.words.is_empty() {
+            return text.to_owned();
+        }
+        let mut result = String::with_capacity(text.len());
+        let mut i = 0;
+        'outer: while let Some(c) = text[i..].chars().next() {
+            for word in &self.words {
+                if let Some(mut end) = Self::match_at(text, i, word) {
+                    match self.profanity {
+                        Profanity::Masked => result.extend(text[i..end].chars().map(|_| '*')),
+                        _ => {
+                            // 删除之后，两边的空格只保留一个，标点之前和行首不保留
+                            let spaces = [' ', '\t'];
+                            let had_space = result.ends_with(spaces) || text[end..].starts_with(spaces);
+                            result.truncate(result.trim_end_matches(spaces).len());
+                            end = text.len() - text[end..].trim_start_matches(spaces).len();
+                            let next = text[end..].chars().next();
+                            let after_word = result.chars().next_back().is_some_and(|c| !c.is_whitespace());
+                            if had_space && after_word && next.is_some_and(|c| !c.is_whitespace() && !",.!?;:)]}".contains(c)) {
+                                result.push(' ');
+                            }
+                        }
+                    }
+                    i = end;
+                    continue 'outer;
+                }
+            }
+            result.push(c);
+            i += c.len_utf8();
+        }
+        result
+    }
+
+    /// 过滤识别结果中的文本
+    pub fn apply_event(&self, event: &mut RecognitionEvent) {
+        match event {
+            RecognitionEvent::Hypothesis(hypothesis) => hypothesis.text = self.apply(&hypothesis.text),
+            RecognitionEvent::Phrase(phrase) => phrase.display_text = self.apply(&phrase.display_text),
+            RecognitionEvent::DetailedPhrase(phrase) => {
+                for entry in &mut phrase.n_best {
+                    entry.lexical = self.apply(&entry.lexical);
+                    entry.itn = self.apply(&entry.itn);
+                    entry.masked_itn = self.apply(&entry.masked_itn);
+                    entry.display = self.apply(&entry.display);
+                    for word in &mut entry.words {
+                        word.word = self.apply(&word.word);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speech_recognition::{DetailedSpeechPhrase, NBestEntry, SpeechHypothesis, Word};
+
+    #[test]
+    fn masks_whole_words_ignoring_ascii_case() {
+        let filter = ProfanityFilter::new(Profanity::Masked, ["darn", "heck"]);
+        assert_eq!(filter.apply("Darn it, what the HECK."), "**** it, what the ****.");
+        assert_eq!(filter.apply("darning and checkers"), "darning and checkers");
+    }
+
+    #[test]
+    fn removes_words_and_extra_spaces() {
+        let filter = ProfanityFilter::new(Profanity::Removed, ["darn"]);
+        assert_eq!(filter.apply("oh darn it"), "oh it");
+        assert_eq!(filter.apply("oh darn darn  it"), "oh it");
+        assert_eq!(filter.apply("Darn it"), "it");
+        assert_eq!(filter.apply("oh darn"), "oh");
+        assert_eq!(filter.apply("oh darn, it"), "oh, it");
+        assert_eq!(filter.apply("oh (darn) it"), "oh () it");
+    }
+
+    #[test]
+    fn removal_keeps_unrelated_spacing() {
+        let filter = ProfanityFilter::new(Profanity::Removed, ["darn"]);
+        assert_eq!(filter.apply("line one\nDarn line two"), "line one\nline two");
+        assert_eq!(filter.apply("line one darn\nline two"), "line one\nline two");
+        assert_eq!(filter.apply("a  b darn c"), "a  b c");
+    }
+
+    #[test]
+    fn matches_cjk_as_substring() {
+        let filter = ProfanityFilter::new(Profanity::Masked, ["笨蛋", "笨"]);
+        assert_eq!(filter.apply("你这个笨蛋"), "你这个**");
+        assert_eq!(filter.apply("很笨"), "很*");
+    }
+
+    #[test]
+    fn raw_keeps_text() {
+        let filter = ProfanityFilter::new(Profanity::Raw, ["darn"]);
+        assert_eq!(filter.apply("darn"), "darn");
+    }
+
+    #[test]
+    fn applies_to_hypothesis_and_detailed_phrase() {
+        let filter = ProfanityFilter::new(Profanity::Masked, ["darn"]);
+        let mut event = RecognitionEvent::Hypothesis(SpeechHypothesis { text: "darn it".into(), ..Default::default() });
+        filter.apply_event(&mut event);
+        match event {
+            RecognitionEvent::Hypothesis(hypothesis) => assert_eq!(hypothesis.text, "**** it"),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let entry = NBestEntry {
+            lexical: "darn it".into(),
+            itn: "darn it".into(),
+            masked_itn: "darn it".into(),
+            display: "Darn it.".into(),
+            words: vec![Word { word: "darn".into(), ..Default::default() }, Word { word: "it".into(), ..Default::default() }],
+            ..Default::default()
+        };
+        let mut event = RecognitionEvent::DetailedPhrase(DetailedSpeechPhrase { n_best: vec![entry.clone(), entry], ..Default::default() });
+        filter.apply_event(&mut event);
+        let phrase = match event {
+            RecognitionEvent::DetailedPhrase(phrase) => phrase,
+            event => panic!("unexpected event {:?}", event),
+        };
+        for entry in &phrase.n_best {
+            assert_eq!([&entry.lexical, &entry.itn, &entry.masked_itn], ["**** it"; 3]);
+            assert_eq!(entry.display, "**** it.");
+            let words: Vec<&str> = entry.words.iter().map(|word| word.word.as_str()).collect();
+            assert_eq!(words, ["****", "it"]);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! 断线后自动重连的会话。
+//!
+//! 最近写入的音频保存在一个有上限的缓冲区中，收到 speech.phrase 之后，这句话之前的音频就不再需要了。
+//! 连接因为网络问题断开时，按指数退避重新连接，把还没有得到结果的音频重新发送一遍，
+//! 并且把新连接的结果的 Offset 调整到同一个时间轴上。
+
+use crate::error::{Error, Result};
+use crate::session_builder::SessionBuilder;
+use crate::speech_recognition::{ticks_to_duration, RecognitionEvent, Session};
+use std::collections::VecDeque;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    replay_duration: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            replay_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// 每次断线后最多尝试重连的次数，默认 5
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// 第一次重连之前等待的时间，之后每次翻倍，默认 500 毫秒
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// 两次重连之间最长的等待时间，默认 30 秒
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// 最多保存多长时间的音频用于重新发送，默认 30 秒。更早的音频即使没有得到结果也会被丢弃
+    pub fn replay_duration(mut self, replay_duration: Duration) -> Self {
+        self.replay_duration = replay_duration;
+        self
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_backoff)
+    }
+}
+
+/// 网络问题或者服务端暂时不可用导致的错误，重连之后可能恢复
+fn is_transport_error(e: &Error) -> bool {
+    match e {
+        Error::Connect(_) | Error::WebSocket(_) => true,
+        Error::Service { cancellation, .. } => cancellation.is_transient(),
+        _ => false,
+    }
+}
+
+/// 出现 `error` 之后，按 `policy` 退避之后重试 `f`，总共最多 max_attempts 次。`f` 返回网络错误以外的错误时立即返回
+fn retry<T>(policy: &ReconnectPolicy, error: Error, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut error = error;
+    for attempt in 0..policy.max_attempts {
+        sleep(policy.backoff(attempt));
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if is_transport_error(&e) => error = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(error)
+}
+
+/// 断线后自动重连的 [`Session`]，只支持 PCM 音频。
+/// 不要手动写入 WAV 头部，使用 [`SessionBuilder::audio_format`] 或 [`SessionBuilder::wave_header`]，
+/// 写入的音频的格式按这两个选项换算成时间，都没有设置时按 [`SessionBuilder::audio_source`]。
+pub struct ResilientSession {
+    builder: SessionBuilder,
+    policy: ReconnectPolicy,
+    session: Session,
+    /// 写入的音频中最近的、还没有得到结果的部分
+    replay: VecDeque<u8>,
+    replay_capacity: usize,
+    /// 写入的音频的总字节数
+    written: u64,
+    /// 当前连接的第一个字节在写入的音频中的位置
+    session_start: u64,
+    /// 写入的音频每秒的字节数和每帧的字节数
+    bytes_per_second: u64,
+    block_align: u64,
+    /// 实际发送的音频每秒的字节数，用于换算 [`Session::audio_bytes_before_turn`]
+    sent_bytes_per_second: u64,
+    audio_ended: bool,
+    /// 收到了最后的 turn.end，或者调用了 close，之后连接关闭不再重连
+    finished: bool,
+    reconnects: u32,
+}
+
+impl ResilientSession {
+    /// # Returns
+    /// * Same as [`SessionBuilder::connect`], after all attempts failed
+    pub fn connect(builder: SessionBuilder, policy: ReconnectPolicy) -> Result<Self> {
+        let sent_format = builder.sent_audio_format();
+        let written_format = builder.audio_format.unwrap_or(sent_format);
+        let bytes_per_second = written_format.sample_rate as u64 * written_format.block_align() as u64;
+        let block_align = (written_format.block_align() as u64).max(1);
+        let replay_capacity = (bytes_per_second as f64 * policy.replay_duration.as_secs_f64()) as u64 / block_align * block_align;
+        let session = match builder.connect() {
+            Ok(session) => session,
+            Err(e) if is_transport_error(&e) => retry(&policy, e, || builder.connect())?,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            session,
+            replay: VecDeque::new(),
+            replay_capacity: replay_capacity as usize,
+            written: 0,
+            session_start: 0,
+            bytes_per_second: bytes_per_second.max(1),
+            block_align,
+            sent_bytes_per_second: (sent_format.sample_rate as u64 * sent_format.block_align() as u64).max(1),
+            audio_ended: false,
+            finished: false,
+            reconnects: 0,
+            builder,
+            policy,
+        })
+    }
+
+    /// 当前这一轮的 X-RequestId，重连之后会变化
+    pub fn request_id(&self) -> &str {
+        self.session.request_id()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.session.is_closed()
+    }
+
+    /// 到目前为止重连成功的次数
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// 出现网络错误时重新连接，并重新发送缓冲区中的音频。连接和重新发送失败都算一次尝试，共用同一个退避序列。其他错误原样返回
+    fn recover(&mut self, error: Error) -> Result<()> {
+        // 还没有收到最后的 turn.end 连接就断开了，也是网络问题
+        let dropped = matches!(error, Error::Closed) && !self.finished;
+        if !dropped && !is_transport_error(&error) {
+            return Err(error);
+        }
+        let policy = self.policy.clone();
+        retry(&policy, error, || self.reconnect())?;
+        self.reconnects += 1;
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        self.session = self.builder.connect()?;
+        self.session_start = self.written - self.replay.len() as u64;
+        let (front, back) = self.replay.as_slices();
+        self.session.write(front)?;
+        self.session.write(back)?;
+        self.session.flush()?;
+        if self.audio_ended {
+            self.session.end_audio()?;
+        }
+        Ok(())
+    }
+
+    /// 丢弃写入的音频中 `position` 之前的部分
+    fn discard_before(&mut self, position: u64) {
+        let position = position / self.block_align * self.block_align;
+        let replay_start = self.written - self.replay.len() as u64;
+        if position > replay_start {
+            let len = ((position - replay_start) as usize).min(self.replay.len());
+            self.replay.drain(..len);
+        }
+    }
+
+    /// # Returns
+    /// * Err(Error::Closed), when the session is already closed, or [`ResilientSession::end_audio`] has been called
+    /// * Others same as [`Session::write`], after reconnecting failed
+    pub fn write(&mut self, data: impl AsRef<[u8]>) -> Result<()> {
+        if self.audio_ended || self.session.is_closed() {
+            return Err(Error::Closed);
+        }
+        let data = data.as_ref();
+        self.replay.extend(data);
+        self.written += data.len() as u64;
+        if self.replay.len() > self.replay_capacity {
+            let position = self.written - self.replay_capacity as u64;
+            // 向上对齐到帧，保证缓冲区从一帧的开头开始
+            self.discard_before(position.div_ceil(self.block_align) * self.block_align);
+        }
+        match self.session.write(data) {
+            Ok(()) => Ok(()),
+            Err(e) => self.recover(e),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match self.session.flush() {
+            Ok(()) => Ok(()),
+            Err(e) => self.recover(e),
+        }
+    }
+
+    /// See [`Session::end_audio`]
+    pub fn end_audio(&mut self) -> Result<()> {
+        self.audio_ended = true;
+        match self.session.end_audio() {
+            Ok(()) => Ok(()),
+            Err(e) => self.recover(e),
+        }
+    }
+
+    /// 发送剩余的音频，然后关闭连接，不会重连
+    pub fn close(&mut self) -> Result<()> {
+        self.finished = true;
+        self.session.close()
+    }
+
+    /// 把结果的 Offset 调整到写入的音频的时间轴上，收到 speech.phrase 时丢弃之前的音频
+    fn handle(&mut self, result: Result<Option<RecognitionEvent>>) -> Result<Option<RecognitionEvent>> {
+        let mut event = match result {
+            Ok(Some(event)) => event,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                self.recover(e)?;
+                return Ok(None);
+            }
+        };
+        let session_offset = Duration::from_secs_f64(self.session_start as f64 / self.bytes_per_second as f64);
+        let turn_offset = Duration::from_secs_f64(self.session.audio_bytes_before_turn() as f64 / self.sent_bytes_per_second as f64);
+        event.shift_offset(session_offset + turn_offset);
+        if event == RecognitionEvent::TurnEnd && self.session.is_closed() {
+            self.finished = true;
+        }
+        let phrase_end = match &event {
+            RecognitionEvent::Phrase(phrase) => Some(phrase.offset + phrase.duration),
+            RecognitionEvent::DetailedPhrase(phrase) => Some(phrase.offset + phrase.duration),
+            _ => None,
+        };
+        if let Some(phrase_end) = phrase_end {
+            let position = ticks_to_duration(phrase_end).as_secs_f64() * self.bytes_per_second as f64;
+            self.discard_before(position as u64);
+        }
+        Ok(Some(event))
+    }
+
+    /// # Returns
+    /// * Same as [`Session::try_recv_message`], Ok(None) after reconnecting
+    pub fn try_recv_message(&mut self) -> Result<Option<RecognitionEvent>> {
+        let result = self.session.try_recv_message();
+        self.handle(result)
+    }
+
+    /// # Returns
+    /// * Same as [`Session::recv_message_timeout`], Ok(None) after reconnecting
+    pub fn recv_message_timeout(&mut self, timeout: Duration) -> Result<Option<RecognitionEvent>> {
+        let result = self.session.recv_message_timeout(timeout);
+        self.handle(result)
+    }
+
+    /// # Returns
+    /// * Same as [`Session::recv_message`], reconnects and keeps waiting on network errors
+    pub fn recv_message(&mut self) -> Result<RecognitionEvent> {
+        loop {
+            let result = self.session.recv_message().map(Some);
+            if let Some(event) = self.handle(result)? {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::time::Instant;
+
+    fn connect_error() -> Error {
+        Error::Connect(io::Error::from(io::ErrorKind::ConnectionRefused))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = ReconnectPolicy::default().initial_backoff(Duration::from_millis(100)).max_backoff(Duration::from_millis(500));
+        let backoffs: Vec<u128> = (0..5).map(|attempt| policy.backoff(attempt).as_millis()).collect();
+        assert_eq!(backoffs, [100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn retry_stops_after_max_attempts() {
+        let policy = ReconnectPolicy::default().max_attempts(3).initial_backoff(Duration::from_millis(20)).max_backoff(Duration::from_millis(30));
+        let mut attempts = 0;
+        let start = Instant::now();
+        let result: Result<()> = retry(&policy, connect_error(), || {
+            attempts += 1;
+            Err(connect_error())
+        });
+        assert!(matches!(result, Err(Error::Connect(_))));
+        assert_eq!(attempts, 3);
+        // 20 + 30 + 30 毫秒
+        assert!(start.elapsed() >= Duration::from_millis(80));
+    }
+
+    #[test]
+    fn retry_returns_first_success() {
+        let policy = ReconnectPolicy::default().initial_backoff(Duration::ZERO);
+        let mut attempts = 0;
+        let result = retry(&policy, connect_error(), || {
+            attempts += 1;
+            if attempts < 2 {
+                Err(connect_error())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[test]
+    fn retry_does_not_retry_other_errors() {
+        let policy = ReconnectPolicy::default().initial_backoff(Duration::ZERO);
+        let mut attempts = 0;
+        let result: Result<()> = retry(&policy, connect_error(), || {
+            attempts += 1;
+            Err(Error::Service {
+                code: 1008,
+                reason: String::new(),
+                cancellation: crate::CancellationReason::Forbidden,
+            })
+        });
+        assert!(matches!(result, Err(Error::Service { code: 1008, .. })));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,702 @@
+#[cfg(feature = "tokio")]
+use crate::async_session::AsyncSession;
+#[cfg(feature = "tokio")]
+use crate::error::Error;
+use crate::audio::{AudioConverter, AudioEncoding, AudioFormat, SampleType};
+use crate::error::Result;
+use crate::profanity::{Profanity, ProfanityFilter};
+use crate::speech_recognition::{build_text_message, connect, get_request_url, random_request_id, OutputFormat, RecognitionMode, Session, FLUSH_SIZE};
+use crate::wav::{read_header, WaveFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use websocket::header::Headers;
+use websocket::url::Url;
+use websocket::Message;
+
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36 Edg/131.0.0.0";
+
+/// speech.config 中的 context.audio.source，描述实际发送的音频
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioSource {
+    #[serde(rename = "bitspersample")]
+    pub bits_per_sample: u16,
+    #[serde(rename = "channelcount")]
+    pub channel_count: u16,
+    pub connectivity: String,
+    pub manufacturer: String,
+    pub model: String,
+    #[serde(rename = "samplerate")]
+    pub sample_rate: u32,
+    #[serde(rename = "type")]
+    pub source_type: String,
+}
+
+impl Default for AudioSource {
+    fn default() -> Self {
+        Self {
+            bits_per_sample: 16,
+            channel_count: 1,
+            connectivity: "Unknown".to_owned(),
+            manufacturer: "Speech SDK".to_owned(),
+            model: "Default - Microphone".to_owned(),
+            sample_rate: 16000,
+            source_type: "Microphones".to_owned(),
+        }
+    }
+}
+
+/// speech.config 中除了 audio 以外的 context，默认模拟浏览器中的 JavaScript Speech SDK
+pub fn default_sdk_context(user_agent: &str) -> serde_json::Value {
+    json!({
+        "system": {
+            "name": "SpeechSDK",
+            "version": "1.15.0-alpha.0.1",
+            "build": "JavaScript",
+            "lang": "JavaScript",
+        },
+        "os": {
+            "platform": "Browser/Win32",
+            "name": user_agent,
+            "version": user_agent.strip_prefix("Mozilla/").unwrap_or(user_agent),
+        },
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authentication {
+    /// 通过 Ocp-Apim-Subscription-Key 请求头发送
+    SubscriptionKey(String),
+    /// 通过 Authorization: Bearer 请求头发送，token 需要调用方自行获取和刷新
+    AuthorizationToken(String),
+}
+
+impl Authentication {
+    pub fn header(&self) -> (&'static str, String) {
+        match self {
+            Authentication::SubscriptionKey(key) => ("Ocp-Apim-Subscription-Key", key.clone()),
+            Authentication::AuthorizationToken(token) => ("Authorization", format!("Bearer {}", token)),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// bing.com 的接口，不需要密钥
+    #[default]
+    Bing,
+    /// Azure 语音服务
+    Azure {
+        /// 例如 "eastus"、"westeurope"
+        region: String,
+        authentication: Authentication,
+    },
+    /// 任意兼容的服务，支持 ws:// 和 wss://，不包含查询参数
+    Custom {
+        url: String,
+        authentication: Option<Authentication>,
+    },
+}
+
+impl Endpoint {
+    pub fn azure(region: &str, authentication: Authentication) -> Self {
+        Endpoint::Azure {
+            region: region.to_owned(),
+            authentication,
+        }
+    }
+
+    pub fn authentication(&self) -> Option<&Authentication> {
+        match self {
+            Endpoint::Bing => None,
+            Endpoint::Azure { authentication, .. } => Some(authentication),
+            Endpoint::Custom { authentication, .. } => authentication.as_ref(),
+        }
+    }
+}
+
+impl From<&str> for Endpoint {
+    fn from(url: &str) -> Self {
+        Endpoint::Custom {
+            url: url.to_owned(),
+            authentication: None,
+        }
+    }
+}
+
+/// 标点的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Punctuation {
+    /// 服务端根据语气自动插入标点
+    Implicit,
+    /// 只插入口述的标点，例如说 "period" 插入 "."，说 "new line" 换行。通常配合 [`RecognitionMode::Dictation`] 使用
+    Explicit,
+}
+
+impl Punctuation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Punctuation::Implicit => "implicit",
+            Punctuation::Explicit => "explicit",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionBuilder {
+    pub(crate) language: String,
+    pub(crate) candidate_languages: Vec<String>,
+    pub(crate) endpoint: Endpoint,
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) user_agent: String,
+    pub(crate) sdk_context: Option<serde_json::Value>,
+    pub(crate) audio_source: AudioSource,
+    pub(crate) audio_format: Option<AudioFormat>,
+    pub(crate) audio_encoding: AudioEncoding,
+    pub(crate) recognition_mode: RecognitionMode,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) word_level_timestamps: bool,
+    pub(crate) punctuation: Option<Punctuation>,
+    pub(crate) phrases: Vec<String>,
+    pub(crate) initial_silence_timeout: Option<Duration>,
+    pub(crate) end_silence_timeout: Option<Duration>,
+    pub(crate) segmentation_silence_timeout: Option<Duration>,
+    pub(crate) profanity: Option<Profanity>,
+    pub(crate) profanity_words: Vec<String>,
+    pub(crate) flush_size: usize,
+    pub(crate) wave_header: Vec<u8>,
+}
+
+impl SessionBuilder {
+    /// # Arguments
+    /// * `language` - "zh-CN", "en-US"
+    pub fn new(language: &str) -> Self {
+        Self {
+            language: language.to_owned(),
+            candidate_languages: Vec::new(),
+            endpoint: Endpoint::default(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            sdk_context: None,
+            audio_source: AudioSource::default(),
+            audio_format: None,
+            audio_encoding: AudioEncoding::default(),
+            recognition_mode: RecognitionMode::default(),
+            output_format: OutputFormat::default(),
+            word_level_timestamps: false,
+            punctuation: None,
+            phrases: Vec::new(),
+            initial_silence_timeout: None,
+            end_silence_timeout: None,
+            segmentation_silence_timeout: None,
+            profanity: None,
+            profanity_words: Vec::new(),
+            flush_size: FLUSH_SIZE,
+            wave_header: Vec::new(),
+        }
+    }
+
+    /// 自动识别语言的候选列表，例如 `["zh-CN", "en-US"]`，识别出的语言在结果的 `primary_language` 中。
+    /// 单轮识别只在开头识别一次语言，连续识别时会持续识别，可以在说话过程中切换语言。
+    pub fn candidate_languages<S: AsRef<str>>(mut self, languages: impl IntoIterator<Item = S>) -> Self {
+        self.candidate_languages = languages.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// 服务地址，默认 [`Endpoint::Bing`]。传入 &str 时作为不需要认证的自定义地址，例如 "ws://127.0.0.1:8080"。
+    pub fn endpoint(mut self, endpoint: impl Into<Endpoint>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// 额外的查询参数，追加在 URL 末尾
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.query.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// 额外的 HTTP 请求头
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// speech.config 中的 context，audio 字段会被 [`SessionBuilder::audio_source`] 覆盖。
+    /// 不设置时使用 [`default_sdk_context`]。
+    pub fn sdk_context(mut self, sdk_context: serde_json::Value) -> Self {
+        self.sdk_context = Some(sdk_context);
+        self
+    }
+
+    pub fn audio_source(mut self, audio_source: AudioSource) -> Self {
+        self.audio_source = audio_source;
+        self
+    }
+
+    /// 写入的音频的格式。设置之后写入的音频会被转换成 16 kHz 16 位单声道再发送，
+    /// speech.config 中的 audio.source 和自动发送的 WAV 头部都和转换后的格式一致，不需要再设置 [`SessionBuilder::wave_header`]。
+    pub fn audio_format(mut self, audio_format: AudioFormat) -> Self {
+        self.audio_format = Some(audio_format);
+        self
+    }
+
+    /// 发送的音频编码，默认 [`AudioEncoding::Wav`]。压缩格式的数据写入后原样发送，
+    /// 此时 [`SessionBuilder::audio_format`] 和 [`SessionBuilder::wave_header`] 都不生效。
+    pub fn audio_encoding(mut self, audio_encoding: AudioEncoding) -> Self {
+        self.audio_encoding = audio_encoding;
+        self
+    }
+
+    pub(crate) fn audio_converter(&self) -> Option<AudioConverter> {
+        if self.audio_encoding.is_compressed() {
+            return None;
+        }
+        self.audio_format.map(AudioConverter::new)
+    }
+
+    /// 每一轮开始时发送的 WAV 头部，设置了 [`SessionBuilder::audio_format`] 时是转换后的格式
+    pub(crate) fn turn_wave_header(&self) -> Vec<u8> {
+        if self.audio_encoding.is_compressed() {
+            return Vec::new();
+        }
+        match self.audio_format {
+            Some(_) => WaveFormat::from(AudioFormat::TARGET).header(None),
+            None => self.wave_header.clone(),
+        }
+    }
+
+    /// 实际发送的 PCM 音频的格式，用于把识别结果的时间换算成音频的字节数。
+    /// 设置了 [`SessionBuilder::audio_format`] 时是转换后的格式，否则按 [`SessionBuilder::wave_header`]，都没有时按 [`SessionBuilder::audio_source`]
+    pub(crate) fn sent_audio_format(&self) -> AudioFormat {
+        if self.audio_format.is_some() {
+            return AudioFormat::TARGET;
+        }
+        if let Some(format) = read_header(&self.wave_header[..]).ok().and_then(|header| header.format.audio_format()) {
+            return format;
+        }
+        let source = &self.audio_source;
+        let sample_type = match source.bits_per_sample {
+            24 => SampleType::I24,
+            32 => SampleType::I32,
+            _ => SampleType::I16,
+        };
+        AudioFormat::new(source.sample_rate, source.channel_count, sample_type)
+    }
+
+    /// [`RecognitionMode::Conversation`] 和 [`RecognitionMode::Dictation`] 是连续识别，收到 turn.end 之后不会关闭连接，而是自动开始新的一轮
+    pub fn recognition_mode(mut self, recognition_mode: RecognitionMode) -> Self {
+        self.recognition_mode = recognition_mode;
+        self
+    }
+
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// 实际请求的结果格式。只有详细格式的结果才有 Words，所以请求了词级时间戳时总是 [`OutputFormat::Detailed`]
+    pub(crate) fn effective_output_format(&self) -> OutputFormat {
+        if self.word_level_timestamps {
+            OutputFormat::Detailed
+        } else {
+            self.output_format
+        }
+    }
+
+    /// 请求每个词的时间戳，结果在 [`NBestEntry::words`](crate::speech_recognition::NBestEntry::words) 中。
+    /// 开启之后总是使用 [`OutputFormat::Detailed`]，speech.phrase 以 [`RecognitionEvent::DetailedPhrase`](crate::RecognitionEvent::DetailedPhrase) 返回
+    pub fn word_level_timestamps(mut self, word_level_timestamps: bool) -> Self {
+        self.word_level_timestamps = word_level_timestamps;
+        self
+    }
+
+    /// 不设置时使用服务端的默认值
+    pub fn punctuation(mut self, punctuation: Punctuation) -> Self {
+        self.punctuation = Some(punctuation);
+        self
+    }
+
+    /// 脏话的处理方式，不设置时使用服务端的默认值 [`Profanity::Masked`]
+    pub fn profanity(mut self, profanity: Profanity) -> Self {
+        self.profanity = Some(profanity);
+        self
+    }
+
+    /// 本地过滤的脏话词表，用于服务端忽略 [`SessionBuilder::profanity`] 的情况。
+    /// 按 [`SessionBuilder::profanity`] 的方式处理识别结果中的这些词，[`Profanity::Raw`] 时不处理。
+    pub fn profanity_words<S: AsRef<str>>(mut self, words: impl IntoIterator<Item = S>) -> Self {
+        self.profanity_words.extend(words.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// 没有需要在本地过滤的脏话时返回 None
+    pub(crate) fn profanity_filter(&self) -> Option<ProfanityFilter> {
+        let profanity = self.profanity.unwrap_or(Profanity::Masked);
+        if profanity == Profanity::Raw || self.profanity_words.is_empty() {
+            return None;
+        }
+        Some(ProfanityFilter::new(profanity, &self.profanity_words))
+    }
+
+    /// 短语列表，例如产品名、人名、专业术语，提高这些词被识别出来的概率，不需要训练模型。
+    /// 作为动态语法（dgi）放在 speech.context 中发送，可以多次调用。
+    pub fn phrases<S: AsRef<str>>(mut self, phrases: impl IntoIterator<Item = S>) -> Self {
+        self.phrases.extend(phrases.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// 添加一个短语，see [`SessionBuilder::phrases`]
+    pub fn phrase(mut self, phrase: &str) -> Self {
+        self.phrases.push(phrase.to_owned());
+        self
+    }
+
+    /// 开始说话之前允许的最长静音，超时后这一轮以 InitialSilenceTimeout 结束。不设置时使用服务端的默认值
+    pub fn initial_silence_timeout(mut self, timeout: Duration) -> Self {
+        self.initial_silence_timeout = Some(timeout);
+        self
+    }
+
+    /// 单轮识别时，说话之后静音多久结束这一轮。调小可以让命令式的应用更快得到结果
+    pub fn end_silence_timeout(mut self, timeout: Duration) -> Self {
+        self.end_silence_timeout = Some(timeout);
+        self
+    }
+
+    /// 连续识别时，静音多久切分出一个 speech.phrase。听写时调大可以避免思考时的停顿把一句话切断
+    pub fn segmentation_silence_timeout(mut self, timeout: Duration) -> Self {
+        self.segmentation_silence_timeout = Some(timeout);
+        self
+    }
+
+    /// 音频缓冲区达到这个字节数后才发送一次，默认 [`FLUSH_SIZE`]
+    pub fn flush_size(mut self, flush_size: usize) -> Self {
+        self.flush_size = flush_size;
+        self
+    }
+
+    /// 每一轮识别开始时自动发送的 WAV 头部。设置之后不要再手动写入 WAV 头部。
+    /// 连续识别时每一轮都需要 WAV 头部，所以应该使用这个选项。
+    pub fn wave_header(mut self, wave_header: impl Into<Vec<u8>>) -> Self {
+        self.wave_header = wave_header.into();
+        self
+    }
+
+    pub fn request_url(&self, x_connection_id: &str) -> Result<Url> {
+        let mut url = match &self.endpoint {
+            Endpoint::Bing => Url::parse(&get_request_url(self.recognition_mode, &random_request_id(), x_connection_id, self.effective_output_format()))?,
+            Endpoint::Azure { region, .. } => Url::parse(&format!("wss://{}.stt.speech.microsoft.com/speech/recognition/{}/cognitiveservices/v1", region, self.recognition_mode.as_str()))?,
+            Endpoint::Custom { url, .. } => Url::parse(url)?,
+        };
+        if self.endpoint != Endpoint::Bing {
+            if self.candidate_languages.is_empty() {
+                url.query_pairs_mut().append_pair("language", &self.language);
+            }
+            url.query_pairs_mut()
+                .append_pair("format", self.effective_output_format().as_str())
+                .append_pair("X-ConnectionId", x_connection_id);
+        }
+        if !self.candidate_languages.is_empty() {
+            url.query_pairs_mut().append_pair("lidEnabled", "true");
+        }
+        if let Some(punctuation) = self.punctuation {
+            url.query_pairs_mut().append_pair("punctuation", punctuation.as_str());
+        }
+        if let Some(profanity) = self.profanity {
+            url.query_pairs_mut().append_pair("profanity", profanity.as_str());
+        }
+        if let Some(timeout) = self.initial_silence_timeout {
+            url.query_pairs_mut().append_pair("initialSilenceTimeoutMs", &timeout.as_millis().to_string());
+        }
+        if let Some(timeout) = self.end_silence_timeout {
+            url.query_pairs_mut().append_pair("endSilenceTimeoutMs", &timeout.as_millis().to_string());
+        }
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(self.query.iter());
+        }
+        Ok(url)
+    }
+
+    /// WebSocket 握手时的请求头
+    pub fn handshake_headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![
+            ("Accept-Language".to_owned(), self.language.clone()),
+            ("User-Agent".to_owned(), self.user_agent.clone()),
+        ];
+        if let Some(authentication) = self.endpoint.authentication() {
+            let (key, value) = authentication.header();
+            headers.push((key.to_owned(), value));
+        }
+        headers.extend(self.headers.iter().cloned());
+        headers
+    }
+
+    /// speech.config 的内容
+    pub fn speech_config(&self) -> serde_json::Value {
+        let mut context = self.sdk_context.clone().unwrap_or_else(|| default_sdk_context(&self.user_agent));
+        let mut audio_source = self.audio_source.clone();
+        if self.audio_format.is_some() {
+            let target = AudioFormat::TARGET;
+            audio_source.sample_rate = target.sample_rate;
+            audio_source.channel_count = target.channels;
+            audio_source.bits_per_sample = (target.sample_type.bytes_per_sample() * 8) as u16;
+        }
+        if let Some(context) = context.as_object_mut() {
+            context.insert("audio".to_owned(), json!({ "source": audio_source }));
+        }
+        json!({
+            "context": context,
+            "recognition": self.recognition_mode.as_str(),
+        })
+    }
+
+    /// speech.context 中的静音超时设置，initialSilenceTimeoutMs 和 endSilenceTimeoutMs 同时也作为查询参数发送
+    fn phrase_detection(&self) -> Option<serde_json::Value> {
+        let mut phrase_detection = serde_json::Map::new();
+        if let Some(timeout) = self.initial_silence_timeout {
+            phrase_detection.insert("initialSilenceTimeout".to_owned(), json!(timeout.as_millis() as u64));
+        }
+        if let Some(timeout) = self.end_silence_timeout {
+            phrase_detection.insert("trailingSilenceTimeout".to_owned(), json!(timeout.as_millis() as u64));
+        }
+        if let Some(timeout) = self.segmentation_silence_timeout {
+            phrase_detection.insert("mode".to_owned(), json!(self.recognition_mode.phrase_detection_mode()));
+            phrase_detection.insert(
+                self.recognition_mode.as_str().to_owned(),
+                json!({
+                    "segmentation": {
+                        "mode": "Custom",
+                        "segmentationSilenceTimeoutMs": timeout.as_millis() as u64,
+                    },
+                }),
+            );
+        }
+        if phrase_detection.is_empty() {
+            None
+        } else {
+            Some(serde_json::Value::Object(phrase_detection))
+        }
+    }
+
+    /// speech.context 的内容
+    pub fn speech_context(&self) -> serde_json::Value {
+        let mut context = serde_json::Map::new();
+        if self.word_level_timestamps {
+            context.insert(
+                "phraseOutput".to_owned(),
+                json!({
+                    "format": "Detailed",
+                    "detailed": {
+                        "options": ["WordTimings"],
+                    },
+                }),
+            );
+        }
+        if !self.candidate_languages.is_empty() {
+            context.insert(
+                "languageId".to_owned(),
+                json!({
+                    "languages": self.candidate_languages,
+                    "onSuccess": { "action": "Recognize" },
+                    "onUnknown": { "action": "None" },
+                    "mode": if self.recognition_mode.is_continuous() { "DetectContinuous" } else { "DetectAtAudioStart" },
+                    "priority": "PrioritizeLatency",
+                }),
+            );
+        }
+        if let Some(phrase_detection) = self.phrase_detection() {
+            context.insert("phraseDetection".to_owned(), phrase_detection);
+        }
+        if !self.phrases.is_empty() {
+            let items: Vec<_> = self.phrases.iter().map(|text| json!({ "Text": text })).collect();
+            context.insert(
+                "dgi".to_owned(),
+                json!({
+                    "Groups": [{ "Type": "Generic", "Items": items }],
+                }),
+            );
+        }
+        serde_json::Value::Object(context)
+    }
+
+    /// # Returns
+    /// * Err(Error::InvalidUrl), when the endpoint is not a valid URL
+    /// * Err(Error::Connect), when the TCP connection fails
+    /// * Err(Error::Handshake), when the TLS or WebSocket handshake fails
+    /// * Err(Error::WebSocket), when sending the initial messages fails
+    pub fn connect(&self) -> Result<Session> {
+        let x_connection_id = random_request_id();
+        let request_id = random_request_id();
+        let request_url = self.request_url(&x_connection_id)?;
+        let mut headers = Headers::new();
+        for (key, value) in self.handshake_headers() {
+            headers.append_raw(key, value.into_bytes());
+        }
+        let mut client = connect(&request_url, &headers)?;
+        client.send_message(&Message::text(build_text_message("speech.config", &request_id, self.speech_config())))?;
+        client.send_message(&Message::text(build_text_message("speech.context", &request_id, self.speech_context())))?;
+        Ok(Session::from_client(client, self, request_id))
+    }
+
+    /// Same as [`SessionBuilder::connect`], but on tokio
+    #[cfg(feature = "tokio")]
+    pub async fn connect_async(&self) -> Result<AsyncSession> {
+        use crate::async_session::{connect_error, ws_error};
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let x_connection_id = random_request_id();
+        let request_id = random_request_id();
+        let request_url = self.request_url(&x_connection_id)?;
+        let mut request = request_url.as_str().into_client_request().map_err(|e| Error::Handshake(Box::new(e)))?;
+        for (key, value) in self.handshake_headers() {
+            let key = HeaderName::from_bytes(key.as_bytes()).map_err(|e| Error::Handshake(Box::new(e)))?;
+            let value = HeaderValue::from_str(&value).map_err(|e| Error::Handshake(Box::new(e)))?;
+            request.headers_mut().append(key, value);
+        }
+        let (mut stream, _) = tokio_tungstenite::connect_async(request).await.map_err(connect_error)?;
+        stream.send(Message::text(build_text_message("speech.config", &request_id, self.speech_config()))).await.map_err(ws_error)?;
+        stream.send(Message::text(build_text_message("speech.context", &request_id, self.speech_context()))).await.map_err(ws_error)?;
+        Ok(AsyncSession::from_stream(stream, self, request_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(url: &Url) -> Vec<(String, String)> {
+        url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned())).collect()
+    }
+
+    #[test]
+    fn bing_url() {
+        let url = SessionBuilder::new("en-US").recognition_mode(RecognitionMode::Conversation).word_level_timestamps(true).request_url("conn").unwrap();
+        assert_eq!(url.host_str(), Some("sr.bing.com"));
+        assert_eq!(url.path(), "/opaluqu/speech/recognition/conversation/cognitiveservices/v1");
+        let query = query(&url);
+        assert!(query.contains(&("format".to_owned(), "detailed".to_owned())));
+        assert!(query.contains(&("X-ConnectionId".to_owned(), "conn".to_owned())));
+        assert!(!query.iter().any(|(k, _)| k == "lidEnabled"));
+    }
+
+    #[test]
+    fn azure_url_and_headers() {
+        let builder = SessionBuilder::new("de-DE")
+            .endpoint(Endpoint::azure("westeurope", Authentication::SubscriptionKey("secret".to_owned())))
+            .recognition_mode(RecognitionMode::Dictation)
+            .user_agent("test-agent")
+            .header("X-Extra", "1");
+        assert_eq!(
+            builder.request_url("conn").unwrap().as_str(),
+            "wss://westeurope.stt.speech.microsoft.com/speech/recognition/dictation/cognitiveservices/v1?language=de-DE&format=simple&X-ConnectionId=conn"
+        );
+        let headers = builder.handshake_headers();
+        let headers: Vec<(&str, &str)> = headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            headers,
+            [("Accept-Language", "de-DE"), ("User-Agent", "test-agent"), ("Ocp-Apim-Subscription-Key", "secret"), ("X-Extra", "1")]
+        );
+    }
+
+    #[test]
+    fn custom_url_with_options() {
+        let builder = SessionBuilder::new("en-US")
+            .endpoint(Endpoint::Custom {
+                url: "ws://127.0.0.1:8080/speech".to_owned(),
+                authentication: Some(Authentication::AuthorizationToken("token".to_owned())),
+            })
+            .candidate_languages(["en-US", "zh-CN"])
+            .punctuation(Punctuation::Explicit)
+            .profanity(Profanity::Removed)
+            .initial_silence_timeout(Duration::from_secs(5))
+            .end_silence_timeout(Duration::from_millis(800))
+            .query("custom", "a b");
+        assert_eq!(
+            builder.request_url("conn").unwrap().as_str(),
+            "ws://127.0.0.1:8080/speech?format=simple&X-ConnectionId=conn&lidEnabled=true&punctuation=explicit&profanity=removed&initialSilenceTimeoutMs=5000&endSilenceTimeoutMs=800&custom=a+b"
+        );
+        assert!(builder.handshake_headers().contains(&("Authorization".to_owned(), "Bearer token".to_owned())));
+    }
+
+    #[test]
+    fn speech_config() {
+        let config = SessionBuilder::new("en-US")
+            .sdk_context(json!({ "system": { "name": "test" } }))
+            .audio_format(AudioFormat::new(48000, 2, SampleType::F32))
+            .recognition_mode(RecognitionMode::Conversation)
+            .speech_config();
+        assert_eq!(
+            config,
+            json!({
+                "context": {
+                    "system": { "name": "test" },
+                    "audio": {
+                        "source": {
+                            "bitspersample": 16,
+                            "channelcount": 1,
+                            "connectivity": "Unknown",
+                            "manufacturer": "Speech SDK",
+                            "model": "Default - Microphone",
+                            "samplerate": 16000,
+                            "type": "Microphones",
+                        },
+                    },
+                },
+                "recognition": "conversation",
+            })
+        );
+    }
+
+    #[test]
+    fn speech_context() {
+        assert_eq!(SessionBuilder::new("en-US").speech_context(), json!({}));
+        let context = SessionBuilder::new("en-US")
+            .recognition_mode(RecognitionMode::Dictation)
+            .word_level_timestamps(true)
+            .candidate_languages(["en-US", "zh-CN"])
+            .phrases(["Contoso", "Jessie"])
+            .initial_silence_timeout(Duration::from_secs(5))
+            .end_silence_timeout(Duration::from_millis(800))
+            .segmentation_silence_timeout(Duration::from_millis(1500))
+            .speech_context();
+        assert_eq!(
+            context,
+            json!({
+                "phraseOutput": {
+                    "format": "Detailed",
+                    "detailed": { "options": ["WordTimings"] },
+                },
+                "languageId": {
+                    "languages": ["en-US", "zh-CN"],
+                    "onSuccess": { "action": "Recognize" },
+                    "onUnknown": { "action": "None" },
+                    "mode": "DetectContinuous",
+                    "priority": "PrioritizeLatency",
+                },
+                "phraseDetection": {
+                    "initialSilenceTimeout": 5000,
+                    "trailingSilenceTimeout": 800,
+                    "mode": "Dictation",
+                    "dictation": {
+                        "segmentation": { "mode": "Custom", "segmentationSilenceTimeoutMs": 1500 },
+                    },
+                },
+                "dgi": {
+                    "Groups": [{ "Type": "Generic", "Items": [{ "Text": "Contoso" }, { "Text": "Jessie" }] }],
+                },
+            })
+        );
+        let interactive = SessionBuilder::new("en-US").candidate_languages(["en-US", "zh-CN"]).speech_context();
+        assert_eq!(interactive["languageId"]["mode"], "DetectAtAudioStart");
+    }
+}
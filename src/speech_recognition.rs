@@ -1,12 +1,23 @@
+use crate::audio::AudioConverter;
+use crate::error::{CancellationReason, Error, Result};
+use crate::background_session::{AudioSender, BackgroundSession, ResultReceiver};
+use crate::profanity::ProfanityFilter;
+use crate::session_builder::SessionBuilder;
+use crate::wav::{riff_header, WaveFormat};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{self, Cursor, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::{Duration, Instant};
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
+use websocket::dataframe::{DataFrame, Opcode};
 use websocket::header::Headers;
-use websocket::native_tls::{TlsConnector, TlsStream};
+use websocket::native_tls::{HandshakeError, TlsConnector, TlsStream};
 use websocket::sync::Client;
+use websocket::url::{ParseError, Url};
+use websocket::ws::util::header::read_header;
+use websocket::ws::Message as _;
 use websocket::{ClientBuilder, Message, OwnedMessage, WebSocketError};
 
 pub fn get_timestamp() -> String {
@@ -19,8 +30,63 @@ pub fn random_request_id() -> String {
     hex::encode_upper(&buf[..])
 }
 
-pub fn get_request_url(uqurequestid: &str, x_connection_id: &str) -> String {
-    format!("wss://sr.bing.com/opaluqu/speech/recognition/interactive/cognitiveservices/v1?clientbuild=bingDesktop&referer=https%3A%2F%2Fwww.bing.com%2F&form=QBLH&uqurequestid={}&language=xx-yy&format=simple&Ocp-Apim-Subscription-Key=key&X-ConnectionId={}", uqurequestid, x_connection_id)
+/// 识别结果的格式
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// speech.phrase 只包含 DisplayText
+    #[default]
+    Simple,
+    /// speech.phrase 包含 NBest 列表，每一项都有 Confidence、Lexical、ITN、MaskedITN、Display
+    Detailed,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Simple => "simple",
+            OutputFormat::Detailed => "detailed",
+        }
+    }
+}
+
+/// 识别模式，决定 URL 路径和 speech.config 中的 recognition 字段
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecognitionMode {
+    /// 短语音，一句话结束后服务端就会结束本轮识别
+    #[default]
+    Interactive,
+    /// 长语音，适合会议等场景
+    Conversation,
+    /// 听写，长语音，支持口述标点，见 [`Punctuation`](crate::session_builder::Punctuation)
+    Dictation,
+}
+
+impl RecognitionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecognitionMode::Interactive => "interactive",
+            RecognitionMode::Conversation => "conversation",
+            RecognitionMode::Dictation => "dictation",
+        }
+    }
+
+    /// speech.context 中 phraseDetection.mode 的取值
+    pub fn phrase_detection_mode(&self) -> &'static str {
+        match self {
+            RecognitionMode::Interactive => "Interactive",
+            RecognitionMode::Conversation => "Conversation",
+            RecognitionMode::Dictation => "Dictation",
+        }
+    }
+
+    /// 连续识别时，一轮结束之后在同一个连接上开始新的一轮
+    pub fn is_continuous(&self) -> bool {
+        *self != RecognitionMode::Interactive
+    }
+}
+
+pub fn get_request_url(recognition_mode: RecognitionMode, uqurequestid: &str, x_connection_id: &str, output_format: OutputFormat) -> String {
+    format!("wss://sr.bing.com/opaluqu/speech/recognition/{}/cognitiveservices/v1?clientbuild=bingDesktop&referer=https%3A%2F%2Fwww.bing.com%2F&form=QBLH&uqurequestid={}&language=xx-yy&format={}&Ocp-Apim-Subscription-Key=key&X-ConnectionId={}", recognition_mode.as_str(), uqurequestid, output_format.as_str(), x_connection_id)
 }
 
 /// # Arguments
@@ -28,55 +94,24 @@ pub fn get_request_url(uqurequestid: &str, x_connection_id: &str) -> String {
 /// * `channels` - 1: mono, 2: stereo
 /// * `samples_per_sec` - 采样率
 /// * `bits_per_sample` - 采样位数
+#[deprecated(note = "use `wav::WaveFormat::header` instead")]
 pub fn build_wave_header(
     format_tag: u16,
     channels: u16,
     samples_per_sec: u32,
     bits_per_sample: u16,
 ) -> Vec<u8> {
-    let block_align = channels * bits_per_sample / 8;
-    let avg_bytes_per_sec = samples_per_sec * block_align as u32;
-    let mut buf = Vec::<u8>::with_capacity(40);
-    buf.write_all(b"RIFF").unwrap();
-    buf.write_all(&[0x00, 0x00, 0x00, 0x00]).unwrap();
-    buf.write_all(b"WAVE").unwrap();
-    buf.write_all(b"fmt ").unwrap();
-    buf.write_all(&[0x10, 0x00, 0x00, 0x00]).unwrap(); // size of WAVEFORMATEX
-    buf.push((format_tag & 0xff) as u8);
-    buf.push(((format_tag >> 8) & 0xff) as u8);
-    buf.push((channels & 0xff) as u8);
-    buf.push(((channels >> 8) & 0xff) as u8);
-    buf.push((samples_per_sec & 0xff) as u8);
-    buf.push(((samples_per_sec >> 8) & 0xff) as u8);
-    buf.push(((samples_per_sec >> 16) & 0xff) as u8);
-    buf.push(((samples_per_sec >> 24) & 0xff) as u8);
-    buf.push((avg_bytes_per_sec & 0xff) as u8);
-    buf.push(((avg_bytes_per_sec >> 8) & 0xff) as u8);
-    buf.push(((avg_bytes_per_sec >> 16) & 0xff) as u8);
-    buf.push(((avg_bytes_per_sec >> 24) & 0xff) as u8);
-    buf.push((block_align & 0xff) as u8);
-    buf.push(((block_align >> 8) & 0xff) as u8);
-    buf.push((bits_per_sample & 0xff) as u8);
-    buf.push(((bits_per_sample >> 8) & 0xff) as u8);
-    buf.write_all(b"data").unwrap();
-    buf
+    let format = WaveFormat::pcm(samples_per_sec, channels, bits_per_sample);
+    let mut fmt_chunk = format.to_fmt_chunk();
+    fmt_chunk[0..2].copy_from_slice(&format_tag.to_le_bytes());
+    riff_header(&fmt_chunk, None)
 }
 
+/// # Arguments
+/// * `wave_format_bytes` - WAVEFORMATEX 或 WAVEFORMATEXTENSIBLE 结构体的字节
+#[deprecated(note = "use `wav::WaveFormat::from_fmt_chunk` and `wav::WaveFormat::header` instead")]
 pub fn build_wave_header_from_wave_format(wave_format_bytes: impl AsRef<[u8]>) -> Vec<u8> {
-    let mut buf = Vec::new();
-    buf.write_all(b"RIFF").unwrap();
-    buf.write_all(&[0x00, 0x00, 0x00, 0x00]).unwrap();
-    buf.write_all(b"WAVE").unwrap();
-    buf.write_all(b"fmt ").unwrap();
-    let wave_format_bytes = wave_format_bytes.as_ref();
-    let wave_format_bytes_len = wave_format_bytes.len();
-    buf.push((wave_format_bytes_len & 0xff) as u8);
-    buf.push(((wave_format_bytes_len >> 8) & 0xff) as u8);
-    buf.push(((wave_format_bytes_len >> 16) & 0xff) as u8);
-    buf.push(((wave_format_bytes_len >> 24) & 0xff) as u8);
-    buf.write_all(wave_format_bytes).unwrap();
-    buf.write_all(b"data").unwrap();
-    buf
+    riff_header(wave_format_bytes.as_ref(), None)
 }
 
 pub fn split_header_body(s: impl AsRef<str>) -> (String, String) {
@@ -90,7 +125,7 @@ pub fn parse_headers(s: impl AsRef<str>) -> Vec<(String, String)> {
     s.as_ref()
         .split("\r\n")
         .filter_map(|s| {
-            if s.len() > 0 {
+            if !s.is_empty() {
                 let mut iter = s.splitn(2, ":");
                 let k = iter.next().unwrap_or("").trim().to_owned();
                 let v = iter.next().unwrap_or("").trim().to_owned();
@@ -102,6 +137,17 @@ pub fn parse_headers(s: impl AsRef<str>) -> Vec<(String, String)> {
         .collect()
 }
 
+/// 开启语言识别时，服务端识别出的语言
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrimaryLanguage {
+    /// 候选语言之一，例如 "zh-CN"
+    #[serde(rename = "Language")]
+    pub language: String,
+    /// "High", "Medium", "Low" 或 "Unknown"
+    #[serde(rename = "Confidence", default)]
+    pub confidence: String,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SpeechHypothesis {
     #[serde(rename = "Text")]
@@ -110,114 +156,1084 @@ pub struct SpeechHypothesis {
     pub offset: i64,
     #[serde(rename = "Duration")]
     pub duration: i64,
+    #[serde(rename = "PrimaryLanguage", default, skip_serializing_if = "Option::is_none")]
+    pub primary_language: Option<PrimaryLanguage>,
+}
+
+/// speech.phrase 的 RecognitionStatus，只有 Success 才有识别出的文本
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum RecognitionStatus {
+    #[default]
+    Success,
+    /// 检测到了语音，但是没有识别出任何词
+    NoMatch,
+    /// 开头只有静音，超过了初始静音超时
+    InitialSilenceTimeout,
+    /// 开头只有噪音
+    BabbleTimeout,
+    /// 服务端出错，或者请求的参数错误
+    Error,
+    /// 听写模式下说了结束听写
+    EndOfDictation,
+    /// 其他未知的状态，原样保留
+    Other(String),
+}
+
+impl RecognitionStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            RecognitionStatus::Success => "Success",
+            RecognitionStatus::NoMatch => "NoMatch",
+            RecognitionStatus::InitialSilenceTimeout => "InitialSilenceTimeout",
+            RecognitionStatus::BabbleTimeout => "BabbleTimeout",
+            RecognitionStatus::Error => "Error",
+            RecognitionStatus::EndOfDictation => "EndOfDictation",
+            RecognitionStatus::Other(status) => status,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == RecognitionStatus::Success
+    }
+
+    /// 服务端出错或者拒绝了请求，和没有识别出语音的 NoMatch、InitialSilenceTimeout 等状态不同。未知的状态也视为错误
+    pub fn is_error(&self) -> bool {
+        matches!(self, RecognitionStatus::Error | RecognitionStatus::Other(_))
+    }
+}
+
+impl From<String> for RecognitionStatus {
+    fn from(status: String) -> Self {
+        match status.as_str() {
+            "Success" => RecognitionStatus::Success,
+            "NoMatch" => RecognitionStatus::NoMatch,
+            "InitialSilenceTimeout" => RecognitionStatus::InitialSilenceTimeout,
+            "BabbleTimeout" => RecognitionStatus::BabbleTimeout,
+            "Error" => RecognitionStatus::Error,
+            "EndOfDictation" => RecognitionStatus::EndOfDictation,
+            _ => RecognitionStatus::Other(status),
+        }
+    }
+}
+
+impl From<RecognitionStatus> for String {
+    fn from(status: RecognitionStatus) -> Self {
+        match status {
+            RecognitionStatus::Other(status) => status,
+            status => status.as_str().to_owned(),
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SpeechPhrase {
     #[serde(rename = "RecognitionStatus")]
-    pub recognition_status: String,
+    pub recognition_status: RecognitionStatus,
     #[serde(rename = "Offset")]
     pub offset: i64,
     #[serde(rename = "Duration")]
     pub duration: i64,
     #[serde(rename = "DisplayText", default)]
     pub display_text: String,
+    #[serde(rename = "PrimaryLanguage", default, skip_serializing_if = "Option::is_none")]
+    pub primary_language: Option<PrimaryLanguage>,
+}
+
+/// 服务端的 Offset 和 Duration 以 100 纳秒为单位
+pub fn ticks_to_duration(ticks: i64) -> Duration {
+    Duration::from_nanos(ticks.max(0) as u64 * 100)
+}
+
+pub fn duration_to_ticks(duration: Duration) -> i64 {
+    (duration.as_nanos() / 100) as i64
+}
+
+mod ticks {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(super::duration_to_ticks(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(super::ticks_to_duration(i64::deserialize(deserializer)?))
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    #[serde(rename = "Word")]
+    pub word: String,
+    #[serde(rename = "Offset", with = "ticks")]
+    pub offset: Duration,
+    #[serde(rename = "Duration", with = "ticks")]
+    pub duration: Duration,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NBestEntry {
+    #[serde(rename = "Confidence", default)]
+    pub confidence: f64,
+    /// 词法形式，例如 "what's the weather like"
+    #[serde(rename = "Lexical", default)]
+    pub lexical: String,
+    /// 反向文本规范化形式，数字、缩写等会被转换，例如 "1 2 3" -> "123"
+    #[serde(rename = "ITN", default)]
+    pub itn: String,
+    /// ITN 形式并且屏蔽了不雅词汇
+    #[serde(rename = "MaskedITN", default)]
+    pub masked_itn: String,
+    /// 显示形式，带标点和大小写
+    #[serde(rename = "Display", default)]
+    pub display: String,
+    /// 只有开启了 `word_level_timestamps` 才会有
+    #[serde(rename = "Words", default)]
+    pub words: Vec<Word>,
+}
+
+/// format=detailed 时的 speech.phrase
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetailedSpeechPhrase {
+    #[serde(rename = "RecognitionStatus")]
+    pub recognition_status: RecognitionStatus,
+    #[serde(rename = "Offset", default)]
+    pub offset: i64,
+    #[serde(rename = "Duration", default)]
+    pub duration: i64,
+    #[serde(rename = "NBest", default)]
+    pub n_best: Vec<NBestEntry>,
+    #[serde(rename = "PrimaryLanguage", default, skip_serializing_if = "Option::is_none")]
+    pub primary_language: Option<PrimaryLanguage>,
+}
+
+impl DetailedSpeechPhrase {
+    /// 置信度最高的候选结果
+    pub fn best(&self) -> Option<&NBestEntry> {
+        self.n_best.iter().max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeechStartDetected {
+    #[serde(rename = "Offset")]
+    pub offset: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeechEndDetected {
+    #[serde(rename = "Offset")]
+    pub offset: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TurnStartContext {
+    #[serde(rename = "serviceTag", default)]
+    pub service_tag: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TurnStart {
+    #[serde(default)]
+    pub context: TurnStartContext,
+}
+
+/// 服务端推送的识别事件。Offset 和 Duration 的单位都是 100 纳秒。
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecognitionEvent {
+    /// speech.hypothesis，中间结果，之后可能还会变化
+    Hypothesis(SpeechHypothesis),
+    /// speech.phrase，一句话的最终结果
+    Phrase(SpeechPhrase),
+    /// format=detailed 时的 speech.phrase
+    DetailedPhrase(DetailedSpeechPhrase),
+    /// speech.startDetected，检测到开始说话
+    StartDetected(SpeechStartDetected),
+    /// speech.endDetected，检测到说话结束
+    EndDetected(SpeechEndDetected),
+    /// turn.start
+    TurnStart(TurnStart),
+    /// turn.end，本轮识别结束
+    TurnEnd,
+    /// 其他未识别的消息，原样返回
+    Unknown {
+        path: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+}
+
+impl RecognitionEvent {
+    /// speech.phrase 的 RecognitionStatus，其他事件返回 None
+    pub fn recognition_status(&self) -> Option<&RecognitionStatus> {
+        match self {
+            RecognitionEvent::Phrase(phrase) => Some(&phrase.recognition_status),
+            RecognitionEvent::DetailedPhrase(phrase) => Some(&phrase.recognition_status),
+            _ => None,
+        }
+    }
+
+    /// 把所有的 Offset 往后移 `by`，用于把多段音频的结果放到同一个时间轴上
+    pub fn shift_offset(&mut self, by: Duration) {
+        let ticks = duration_to_ticks(by);
+        match self {
+            RecognitionEvent::Hypothesis(hypothesis) => hypothesis.offset += ticks,
+            RecognitionEvent::Phrase(phrase) => phrase.offset += ticks,
+            RecognitionEvent::DetailedPhrase(phrase) => {
+                phrase.offset += ticks;
+                for word in phrase.n_best.iter_mut().flat_map(|entry| entry.words.iter_mut()) {
+                    word.offset += by;
+                }
+            }
+            RecognitionEvent::StartDetected(start) => start.offset += ticks,
+            RecognitionEvent::EndDetected(end) => end.offset += ticks,
+            _ => {}
+        }
+    }
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(path: &str, body: &'a str) -> Result<T> {
+    serde_json::from_str(body).map_err(|e| Error::Parse {
+        path: path.to_owned(),
+        source: e,
+    })
+}
+
+/// 解析服务端发来的文本消息。消息格式是 HTTP 风格的头部，空行，然后是 JSON 正文。
+/// speech.phrase 的正文格式取决于请求时的 `output_format`。
+pub fn parse_message(text: impl AsRef<str>, output_format: OutputFormat) -> Result<RecognitionEvent> {
+    let (header_text, body) = split_header_body(text);
+    let headers = parse_headers(header_text);
+    let path = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("Path"))
+        .map(|(_, v)| v.clone())
+        .unwrap_or_default();
+    let event = match path.as_str() {
+        "speech.hypothesis" => RecognitionEvent::Hypothesis(parse_body(&path, &body)?),
+        "speech.phrase" => match output_format {
+            OutputFormat::Simple => RecognitionEvent::Phrase(parse_body(&path, &body)?),
+            OutputFormat::Detailed => RecognitionEvent::DetailedPhrase(parse_body(&path, &body)?),
+        },
+        "speech.startDetected" => RecognitionEvent::StartDetected(parse_body(&path, &body)?),
+        "speech.endDetected" => RecognitionEvent::EndDetected(parse_body(&path, &body)?),
+        "turn.start" => RecognitionEvent::TurnStart(parse_body(&path, &body)?),
+        "turn.end" => RecognitionEvent::TurnEnd,
+        _ => RecognitionEvent::Unknown { path, headers, body },
+    };
+    Ok(event)
 }
 
 pub const FLUSH_SIZE: usize = 3300;
 
-pub struct Session {
-    client: Client<TlsStream<TcpStream>>,
+/// 发送给服务端的文本消息：头部，空行，JSON 正文
+pub fn build_text_message(path: &str, request_id: &str, body: impl std::fmt::Display) -> String {
+    format!("Path: {}\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\nContent-Type: application/json\r\n\r\n{}", path, request_id, get_timestamp(), body)
+}
+
+/// 二进制 audio 消息的前缀：2 字节大端序的头部长度，然后是头部文本，后面直接跟音频数据。
+/// 只有第一个 audio 消息需要 Content-Type。
+pub fn build_audio_message_prefix(request_id: &str, content_type: Option<&str>) -> Vec<u8> {
+    let mut header = format!("Path: audio\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\n", request_id, get_timestamp());
+    if let Some(content_type) = content_type {
+        header.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    let header_bytes_len = header.len();
+    let mut buf = Vec::with_capacity(2 + header_bytes_len);
+    buf.push(((header_bytes_len >> 8) & 0xff) as u8);
+    buf.push((header_bytes_len & 0xff) as u8);
+    buf.extend_from_slice(header.as_bytes());
+    buf
+}
+
+/// 服务端关闭连接。1000 是正常关闭，其他关闭码视为服务端报告的错误。
+pub fn close_error(code: u16, reason: String) -> Error {
+    if code == 1000 {
+        Error::Closed
+    } else {
+        Error::Service {
+            code,
+            reason,
+            cancellation: CancellationReason::from_close_code(code),
+        }
+    }
+}
+
+/// ws:// 使用 TCP 连接，wss:// 使用 TLS 连接
+pub enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream>),
+}
+
+impl MaybeTlsStream {
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            MaybeTlsStream::Plain(s) => s,
+            MaybeTlsStream::Tls(s) => s.get_ref(),
+        }
+    }
+}
+
+impl Read for MaybeTlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            MaybeTlsStream::Plain(s) => s.read(buf),
+            MaybeTlsStream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for MaybeTlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            MaybeTlsStream::Plain(s) => s.write(buf),
+            MaybeTlsStream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            MaybeTlsStream::Plain(s) => s.flush(),
+            MaybeTlsStream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// 建立 TCP 连接、TLS 握手、WebSocket 握手，分别对应不同的错误类型
+pub(crate) fn connect(url: &Url, headers: &Headers) -> Result<Client<MaybeTlsStream>> {
+    let host = url.host_str().ok_or(Error::InvalidUrl(ParseError::EmptyHost))?;
+    let port = url.port_or_known_default().ok_or(Error::InvalidUrl(ParseError::InvalidPort))?;
+    let tcp_stream = TcpStream::connect((host, port)).map_err(Error::Connect)?;
+    let stream = if url.scheme() == "ws" {
+        MaybeTlsStream::Plain(tcp_stream)
+    } else {
+        let connector = TlsConnector::new().map_err(|e| Error::Handshake(Box::new(e)))?;
+        let tls_stream = connector.connect(host, tcp_stream).map_err(|e| match e {
+            HandshakeError::Failure(e) => Error::Handshake(Box::new(e)),
+            HandshakeError::WouldBlock(_) => Error::Handshake("TLS handshake interrupted".into()),
+        })?;
+        MaybeTlsStream::Tls(tls_stream)
+    };
+    let client = ClientBuilder::from_url(url)
+        .custom_headers(headers)
+        .connect_on(stream)
+        .map_err(|e| Error::Handshake(Box::new(e)))?;
+    Ok(client)
+}
+
+/// 服务端的消息都很小，超过这个长度的帧视为协议错误
+const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// 从 socket 读取 WebSocket 消息。读到的字节先放在缓冲区中，凑够一个完整的帧才解析，
+/// 读取超时或者非阻塞读取没有数据时，已经读到的半个帧留在缓冲区中，下次接着读，不会打乱数据流。
+#[derive(Default)]
+struct MessageReader {
+    buffer: Vec<u8>,
+    /// 分片的消息中已经收到的帧
+    fragments: Vec<DataFrame>,
+}
+
+impl MessageReader {
+    /// 取出缓冲区开头的一个完整的帧，数据不够时返回 None
+    fn take_frame(&mut self) -> Result<Option<DataFrame>> {
+        let mut cursor = Cursor::new(&self.buffer[..]);
+        let header = match read_header(&mut cursor) {
+            Ok(header) => header,
+            Err(WebSocketError::NoDataAvailable) => return Ok(None),
+            Err(WebSocketError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if header.len > MAX_FRAME_SIZE {
+            return Err(WebSocketError::ProtocolError("data frame is too large").into());
+        }
+        if Opcode::new(header.opcode).is_none() {
+            return Err(WebSocketError::ProtocolError("unsupported data frame opcode").into());
+        }
+        let header_len = cursor.position() as usize;
+        let frame_len = header_len + header.len as usize;
+        if self.buffer.len() < frame_len {
+            return Ok(None);
+        }
+        let body = self.buffer[header_len..frame_len].to_vec();
+        self.buffer.drain(..frame_len);
+        Ok(Some(DataFrame::read_dataframe_body(header, body, false)?))
+    }
+
+    /// 缓冲区中的下一条完整的消息，控制帧可以夹在分片消息的中间
+    fn next_message(&mut self) -> Result<Option<OwnedMessage>> {
+        while let Some(frame) = self.take_frame()? {
+            if frame.opcode as u8 >= 8 {
+                return Ok(Some(OwnedMessage::from_dataframes(vec![frame])?));
+            }
+            if (frame.opcode == Opcode::Continuation) == self.fragments.is_empty() {
+                return Err(WebSocketError::ProtocolError("unexpected data frame opcode").into());
+            }
+            let finished = frame.finished;
+            self.fragments.push(frame);
+            if finished {
+                return Ok(Some(OwnedMessage::from_dataframes(std::mem::take(&mut self.fragments))?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 从 `reader` 读取一次，返回读到的字节数，0 表示连接已经断开
+    fn read_from(&mut self, reader: &mut dyn Read) -> io::Result<usize> {
+        let mut buf = [0u8; 4096];
+        let len = reader.read(&mut buf)?;
+        self.buffer.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// [`Session`] 和 [`AsyncSession`](crate::AsyncSession) 共用的音频缓冲和轮次状态。
+/// 只负责生成要发送的消息和解析收到的消息，实际的收发由会话完成。
+pub(crate) struct TurnState {
+    output_format: OutputFormat,
+    continuous: bool,
+    /// 每一轮开始时发送的 speech.context 正文
+    speech_context: String,
+    /// 每一轮第一个 audio 消息的 Content-Type
+    content_type: String,
+    wave_header: Vec<u8>,
     request_id: String,
     buffer: Vec<u8>,
+    /// buffer 中 audio 消息头部的长度，超过这个长度才说明有待发送的音频
+    prefix_len: usize,
+    flush_size: usize,
+    /// 设置了 [`SessionBuilder::audio_format`] 时，把写入的音频转换成 16 kHz 16 位单声道
+    converter: Option<AudioConverter>,
+    profanity_filter: Option<ProfanityFilter>,
+    /// 这一轮已经发送的音频字节数，包括 WAV 头部
+    turn_audio_bytes: u64,
+    /// 之前各轮已经识别完的音频字节数，不含 WAV 头部
+    audio_bytes_before_turn: u64,
+    /// PCM 音频每秒的字节数和每帧的字节数，压缩格式时为 0，不能按时间截取
+    bytes_per_second: u64,
+    block_align: u64,
+    /// 这一轮已经发送、但还没有被 speech.phrase 覆盖的音频，不含 WAV 头部。
+    /// 服务端结束这一轮时，这些音频还没有识别，要在新的一轮中重新发送
+    unacknowledged: Vec<u8>,
+    /// 这一轮中被 speech.phrase 覆盖的音频字节数
+    acknowledged_bytes: u64,
+    /// 这一轮收到过 speech.phrase
+    recognized: bool,
+    /// 调用了 end_audio，之后不能再写入音频
+    audio_ended: bool,
+    /// 这一轮已经发送了表示音频结束的空 audio 消息
+    end_of_audio_sent: bool,
+}
+
+/// 等待 speech.phrase 确认的音频最多保留这么长，超过的部分视为已经识别完
+const MAX_UNACKNOWLEDGED_DURATION: Duration = Duration::from_secs(60);
+
+impl TurnState {
+    /// 已经发送完 speech.config 和第一轮的 speech.context 之后调用
+    pub(crate) fn new(builder: &SessionBuilder, request_id: String) -> Self {
+        let sent_format = builder.sent_audio_format();
+        let (bytes_per_second, block_align) = match builder.audio_encoding.is_compressed() {
+            true => (0, 1),
+            false => (sent_format.sample_rate as u64 * sent_format.block_align() as u64, (sent_format.block_align() as u64).max(1)),
+        };
+        let mut state = Self {
+            output_format: builder.effective_output_format(),
+            continuous: builder.recognition_mode.is_continuous(),
+            speech_context: builder.speech_context().to_string(),
+            content_type: builder.audio_encoding.content_type().to_owned(),
+            wave_header: builder.turn_wave_header(),
+            request_id,
+            buffer: Vec::with_capacity(builder.flush_size),
+            prefix_len: 0,
+            flush_size: builder.flush_size,
+            converter: builder.audio_converter(),
+            profanity_filter: builder.profanity_filter(),
+            turn_audio_bytes: 0,
+            audio_bytes_before_turn: 0,
+            bytes_per_second,
+            block_align,
+            unacknowledged: Vec::new(),
+            acknowledged_bytes: 0,
+            recognized: false,
+            audio_ended: false,
+            end_of_audio_sent: false,
+        };
+        state.start_turn_buffer();
+        state
+    }
+
+    /// 每一轮的第一个 audio 消息带有 Content-Type，PCM 音频还带有 WAV 头部
+    fn start_turn_buffer(&mut self) {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&build_audio_message_prefix(&self.request_id, Some(&self.content_type)));
+        self.prefix_len = self.buffer.len();
+        self.buffer.extend_from_slice(&self.wave_header);
+    }
+
+    pub(crate) fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    pub(crate) fn audio_bytes_before_turn(&self) -> u64 {
+        self.audio_bytes_before_turn
+    }
+
+    pub(crate) fn audio_ended(&self) -> bool {
+        self.audio_ended
+    }
+
+    /// 调用过 end_audio，但这一轮还没有发送表示音频结束的消息，也就是重新发送了上一轮没有识别的音频
+    pub(crate) fn end_of_audio_pending(&self) -> bool {
+        self.audio_ended && !self.end_of_audio_sent
+    }
+
+    pub(crate) fn push_audio(&mut self, data: &[u8]) {
+        match &mut self.converter {
+            Some(converter) => self.buffer.extend_from_slice(&converter.convert(data)),
+            None => self.buffer.extend_from_slice(data),
+        }
+    }
+
+    /// 缓冲区达到 flush_size，应该发送
+    pub(crate) fn is_full(&self) -> bool {
+        self.buffer.len() >= self.flush_size
+    }
+
+    pub(crate) fn has_pending_audio(&self) -> bool {
+        self.buffer.len() > self.prefix_len
+    }
+
+    /// 取出缓冲区中待发送的 audio 消息，没有待发送的音频时返回 None
+    pub(crate) fn take_audio_message(&mut self) -> Option<Vec<u8>> {
+        if !self.has_pending_audio() {
+            return None;
+        }
+        if self.bytes_per_second > 0 {
+            // 跳过这一轮开头的 WAV 头部
+            let header_left = (self.wave_header.len() as u64).saturating_sub(self.turn_audio_bytes) as usize;
+            let audio = &self.buffer[self.prefix_len..];
+            self.unacknowledged.extend_from_slice(&audio[header_left.min(audio.len())..]);
+            let max_len = (self.bytes_per_second as f64 * MAX_UNACKNOWLEDGED_DURATION.as_secs_f64()) as u64 / self.block_align * self.block_align;
+            if self.unacknowledged.len() as u64 > max_len {
+                self.acknowledge(self.acknowledged_bytes + (self.unacknowledged.len() as u64 - max_len));
+            }
+        }
+        self.turn_audio_bytes += (self.buffer.len() - self.prefix_len) as u64;
+        let prefix = build_audio_message_prefix(&self.request_id, None);
+        self.prefix_len = prefix.len();
+        Some(std::mem::replace(&mut self.buffer, prefix))
+    }
+
+    /// 表示音频结束的空 audio 消息，之后不能再写入音频。需要先发送 [`TurnState::take_audio_message`] 取出的音频
+    pub(crate) fn end_audio_message(&mut self) -> Vec<u8> {
+        self.audio_ended = true;
+        self.end_of_audio_sent = true;
+        build_audio_message_prefix(&self.request_id, None)
+    }
+
+    /// 这一轮开头的 `position` 字节已经识别完，不需要再重新发送
+    fn acknowledge(&mut self, position: u64) {
+        let position = position / self.block_align * self.block_align;
+        if position > self.acknowledged_bytes {
+            let len = ((position - self.acknowledged_bytes) as usize).min(self.unacknowledged.len());
+            self.unacknowledged.drain(..len);
+            self.acknowledged_bytes = position;
+        }
+    }
+
+    /// 解析服务端的文本消息，并过滤脏话。speech.phrase 结束之前的音频视为已经识别完
+    pub(crate) fn parse(&mut self, text: &str) -> Result<RecognitionEvent> {
+        let mut event = parse_message(text, self.output_format)?;
+        let phrase_end = match &event {
+            RecognitionEvent::Phrase(phrase) => Some(phrase.offset + phrase.duration),
+            RecognitionEvent::DetailedPhrase(phrase) => Some(phrase.offset + phrase.duration),
+            _ => None,
+        };
+        if let Some(ticks) = phrase_end {
+            self.recognized = true;
+            let position = (ticks_to_duration(ticks).as_secs_f64() * self.bytes_per_second as f64) as u64;
+            self.acknowledge(position);
+        }
+        if let Some(filter) = &self.profanity_filter {
+            filter.apply_event(&mut event);
+        }
+        Ok(event)
+    }
+
+    /// 收到 turn.end 之后调用。连续识别时用新的 X-RequestId 开始新的一轮，返回需要在音频之前发送的 speech.context 消息，
+    /// 否则返回 None，会话应该关闭。
+    ///
+    /// 服务端结束这一轮时，可能已经收到了这一轮之后的音频，这些音频不会再被识别。所以最后一个 speech.phrase 之后发送的音频
+    /// 和还没发送的音频一起留给新的一轮，调用过 end_audio 时也要开始新的一轮，需要再发送一次表示音频结束的消息，
+    /// 见 [`TurnState::end_of_audio_pending`]。这一轮没有识别出任何 speech.phrase 时，不再重新发送，避免一直重复同一段音频
+    pub(crate) fn end_turn(&mut self) -> Option<String> {
+        if !self.continuous {
+            return None;
+        }
+        if self.bytes_per_second == 0 {
+            // 压缩格式不能按时间截取，已经发送的音频都视为识别完
+            self.acknowledged_bytes = self.turn_audio_bytes;
+        } else if !self.recognized {
+            self.acknowledged_bytes += self.unacknowledged.len() as u64;
+            self.unacknowledged.clear();
+        }
+        let mut pending_audio = self.buffer.split_off(self.prefix_len);
+        if self.turn_audio_bytes == 0 {
+            // 这一轮还没有发送过音频，WAV 头部还在 buffer 中
+            pending_audio.drain(..self.wave_header.len().min(pending_audio.len()));
+        }
+        if self.audio_ended && self.unacknowledged.is_empty() && pending_audio.is_empty() {
+            return None;
+        }
+        self.audio_bytes_before_turn += self.acknowledged_bytes;
+        self.acknowledged_bytes = 0;
+        self.recognized = false;
+        self.turn_audio_bytes = 0;
+        self.end_of_audio_sent = false;
+        self.request_id = random_request_id();
+        self.start_turn_buffer();
+        self.buffer.append(&mut self.unacknowledged);
+        self.buffer.extend_from_slice(&pending_audio);
+        Some(build_text_message("speech.context", &self.request_id, &self.speech_context))
+    }
+}
+
+pub struct Session {
+    client: Client<MaybeTlsStream>,
+    incoming: MessageReader,
+    turn: TurnState,
+    closed: bool,
 }
 
 impl Session {
+    /// Connects with the default settings, use [`SessionBuilder`] for more options.
+    ///
     /// # Arguments
     /// * `default_language` - "zh-CN", "en-US"
     /// # Returns
-    /// * Err, when websocket error occurs
-    pub fn new(default_language: &str) -> anyhow::Result<Self> {
-        let uqurequestid = random_request_id();
-        let x_connection_id = random_request_id();
-        let request_id = random_request_id();
-        let request_url = get_request_url(&uqurequestid, &x_connection_id);
-        let mut headers = Headers::new();
-        headers.append_raw("Accept-Language", default_language.as_bytes().to_vec());
-        headers.append_raw("User-Agent", b"Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36 Edg/131.0.0.0".to_vec());
-        let mut client = ClientBuilder::new(&request_url)?
-            .custom_headers(&headers)
-            .connect_secure(Some(TlsConnector::new().unwrap()))?;
-        let _ = client.set_nonblocking(true);
-        client.send_message(&Message::text(format!("Path: speech.config\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\nContent-Type: application/json\r\n\r\n{}", request_id, get_timestamp(), r#"{"context":{"system":{"name":"SpeechSDK","version":"1.15.0-alpha.0.1","build":"JavaScript","lang":"JavaScript"},"os":{"platform":"Browser/Win32","name":"Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36 Edg/131.0.0.0","version":"5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36 Edg/131.0.0.0"},"audio":{"source":{"bitspersample":16,"channelcount":1,"connectivity":"Unknown","manufacturer":"Speech SDK","model":"Default - Microphone","samplerate":16000,"type":"Microphones"}}},"recognition":"interactive"}"#)))?;
-        client.send_message(&Message::text(format!("Path: speech.context\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\nContent-Type: application/json\r\n\r\n{}", request_id, get_timestamp(), "{}")))?;
-        let mut buffer = Vec::with_capacity(FLUSH_SIZE);
-        let header = format!("Path: audio\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\nContent-Type: audio/x-wav\r\n", &request_id, get_timestamp());
-        let header_bytes_len = header.len();
-        buffer.push(((header_bytes_len >> 8) & 0xff) as u8);
-        buffer.push((header_bytes_len & 0xff) as u8);
-        buffer.write_all(header.as_bytes())?;
-        Ok(Self {
+    /// * Err(Error::Connect), when the TCP connection fails
+    /// * Err(Error::Handshake), when the TLS or WebSocket handshake fails
+    /// * Err(Error::WebSocket), when sending the initial messages fails
+    pub fn new(default_language: &str) -> Result<Self> {
+        SessionBuilder::new(default_language).connect()
+    }
+
+    /// 已经发送完 speech.config 和 speech.context 之后，构造会话
+    pub(crate) fn from_client(client: Client<MaybeTlsStream>, builder: &SessionBuilder, request_id: String) -> Self {
+        Self {
             client,
-            request_id,
-            buffer,
-        })
+            incoming: MessageReader::default(),
+            turn: TurnState::new(builder, request_id),
+            closed: false,
+        }
     }
 
-    pub fn write(&mut self, data: impl AsRef<[u8]>) -> anyhow::Result<()> {
-        self.buffer.extend_from_slice(data.as_ref());
-        if self.buffer.len() >= FLUSH_SIZE {
+    /// 当前这一轮的 X-RequestId
+    pub fn request_id(&self) -> &str {
+        self.turn.request_id()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// 当前这一轮之前已经发送的音频字节数，不含 WAV 头部。
+    /// 连续识别时每一轮结果的 Offset 从 0 开始，加上这些音频的时长就是在整段音频中的位置。
+    pub fn audio_bytes_before_turn(&self) -> u64 {
+        self.turn.audio_bytes_before_turn()
+    }
+
+    fn shutdown(&mut self) {
+        self.closed = true;
+        let _ = self.client.stream_ref().tcp_stream().shutdown(Shutdown::Both);
+    }
+
+    /// 发送剩余的音频，然后关闭连接。连续识别时用来结束会话。
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        let result = self.flush().and_then(|_| Ok(self.client.send_message(&Message::close())?));
+        self.shutdown();
+        result
+    }
+
+    /// # Returns
+    /// * Err(Error::Closed), when the session is already closed, or [`Session::end_audio`] has been called
+    /// * Err(Error::WebSocket), when sending audio fails
+    pub fn write(&mut self, data: impl AsRef<[u8]>) -> Result<()> {
+        if self.closed || self.turn.audio_ended() {
+            return Err(Error::Closed);
+        }
+        self.turn.push_audio(data.as_ref());
+        if self.turn.is_full() {
             self.flush()?;
         }
         Ok(())
     }
 
-    pub fn flush(&mut self) -> anyhow::Result<()> {
-        if self.buffer.len() > 0 {
-            self.client.send_message(&Message::binary(self.buffer.clone()))?;
-            self.buffer.clear();
-            let header = format!("Path: audio\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\n", &self.request_id, get_timestamp());
-            let header_bytes_len = header.len();
-            self.buffer.push(((header_bytes_len >> 8) & 0xff) as u8);
-            self.buffer.push((header_bytes_len & 0xff) as u8);
-            self.buffer.write_all(header.as_bytes())?;
+    pub fn flush(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::Closed);
+        }
+        if let Some(message) = self.turn.take_audio_message() {
+            self.client.send_message(&Message::binary(message))?;
         }
         Ok(())
     }
 
+    /// 发送剩余的音频，然后发送一个空的 audio 消息，告诉服务端音频已经结束。
+    /// 之后不能再写入音频，服务端返回最后的结果和 turn.end 之后会话关闭。连续识别时，如果服务端提前结束了这一轮，
+    /// 没有识别的音频会在新的一轮中重新发送，直到所有音频都识别完。
+    ///
     /// # Returns
-    /// * Err(anyhow::Error), when websocket error occurs
-    /// * Ok(None), when no message is available
-    /// * Ok(Some((text, is_final))) When is_final is false, it's a partial text. This part of text may change in the final result.
-    pub fn try_recv_message(&mut self) -> anyhow::Result<Option<(String, bool)>> {
-        match self.client.recv_message() {
-            Ok(msg) => {
-                if let OwnedMessage::Text(text) = msg {
-                    let (header_text, body_text) = split_header_body(text.as_str());
-                    let headers = parse_headers(header_text);
-                    for (key, value) in headers.iter() {
-                        if key == "Path" && value == "speech.hypothesis" {
-                            let v = serde_json::from_str::<SpeechHypothesis>(&body_text)?;
-                            return Ok(Some((v.text, false)));
-                        } else if key == "Path" && value == "speech.phrase" {
-                            let v = serde_json::from_str::<SpeechPhrase>(&body_text)?;
-                            return Ok(Some((v.display_text, true)));
-                        } else if key == "Path" && value == "turn.end" {
-                            self.client.shutdown()?;
-                            return Ok(None);
+    /// * Err(Error::Closed), when the session is already closed
+    /// * Err(Error::WebSocket), when sending audio fails
+    pub fn end_audio(&mut self) -> Result<()> {
+        if self.turn.audio_ended() {
+            return Ok(());
+        }
+        self.flush()?;
+        let message = self.turn.end_audio_message();
+        self.client.send_message(&Message::binary(message))?;
+        Ok(())
+    }
+
+    /// 读取一条识别事件，跳过 Ping、Pong 等其他消息。socket 的阻塞方式由调用方事先设置好，没有数据或者到了 `deadline` 时返回 Ok(None)。
+    fn recv(&mut self, deadline: Option<Instant>) -> Result<Option<RecognitionEvent>> {
+        loop {
+            if self.closed {
+                return Err(Error::Closed);
+            }
+            let message = match self.recv_raw(deadline)? {
+                Some(message) => message,
+                None => return Ok(None),
+            };
+            match message {
+                OwnedMessage::Text(text) => {
+                    let event = self.turn.parse(&text)?;
+                    if event == RecognitionEvent::TurnEnd {
+                        match self.turn.end_turn() {
+                            Some(context) => {
+                                self.client.send_message(&Message::text(context))?;
+                                if self.turn.end_of_audio_pending() {
+                                    self.flush()?;
+                                    let message = self.turn.end_audio_message();
+                                    self.client.send_message(&Message::binary(message))?;
+                                }
+                            }
+                            None => self.shutdown(),
                         }
                     }
+                    return Ok(Some(event));
                 }
-                Ok(None)
+                OwnedMessage::Close(close_data) => {
+                    self.shutdown();
+                    return Err(close_data.map_or(Error::Closed, |d| close_error(d.status_code, d.reason)));
+                }
+                OwnedMessage::Ping(data) => self.client.send_message(&Message::pong(data))?,
+                _ => {}
+            }
+        }
+    }
+
+    /// 读取一条 WebSocket 消息，没有数据或者到了 `deadline` 时返回 Ok(None)。连接断开时返回 Err(Error::Closed)
+    fn recv_raw(&mut self, deadline: Option<Instant>) -> Result<Option<OwnedMessage>> {
+        loop {
+            if let Some(message) = self.incoming.next_message()? {
+                return Ok(Some(message));
             }
-            Err(e) => {
-                if let WebSocketError::IoError(e) = &e {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        return Ok(None);
+            if let Some(deadline) = deadline {
+                match deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+                    Some(remaining) => {
+                        let _ = self.client.stream_ref().tcp_stream().set_read_timeout(Some(remaining));
                     }
+                    None => return Ok(None),
+                }
+            }
+            match self.incoming.read_from(self.client.reader_mut()) {
+                Ok(0) => {
+                    // 没有收到关闭帧就断开了
+                    self.shutdown();
+                    return Err(Error::Closed);
                 }
-                Err(e.into())
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(WebSocketError::IoError(e).into()),
+            }
+        }
+    }
+
+    /// Returns immediately.
+    ///
+    /// # Returns
+    /// * Err(Error::WebSocket), when websocket error occurs
+    /// * Err(Error::Parse), when a result message can't be parsed
+    /// * Err(Error::Service), when the service closes the connection with an error code
+    /// * Err(Error::Closed), when the session is already closed, or the connection drops without a close frame
+    /// * Ok(None), when no message is available
+    /// * Ok(Some(event)), see [`RecognitionEvent`]. After `TurnEnd` the session is closed, unless the recognition mode is continuous.
+    pub fn try_recv_message(&mut self) -> Result<Option<RecognitionEvent>> {
+        let tcp_stream = self.client.stream_ref().tcp_stream();
+        let _ = tcp_stream.set_nonblocking(true);
+        let result = self.recv(None);
+        let _ = self.client.stream_ref().tcp_stream().set_nonblocking(false);
+        result
+    }
+
+    /// Waits at most `timeout` for a message.
+    ///
+    /// # Returns
+    /// * Same as [`Session::try_recv_message`], Ok(None) when the timeout elapses
+    pub fn recv_message_timeout(&mut self, timeout: Duration) -> Result<Option<RecognitionEvent>> {
+        if timeout.is_zero() {
+            return self.try_recv_message();
+        }
+        let result = self.recv(Some(Instant::now() + timeout));
+        let _ = self.client.stream_ref().tcp_stream().set_read_timeout(None);
+        result
+    }
+
+    /// Blocks until a message is available.
+    ///
+    /// # Returns
+    /// * Same as [`Session::try_recv_message`], but never Ok(None)
+    pub fn recv_message(&mut self) -> Result<RecognitionEvent> {
+        let _ = self.client.stream_ref().tcp_stream().set_read_timeout(None);
+        loop {
+            if let Some(event) = self.recv(None)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// 把会话交给后台线程，通过 channel 收发，见 [`BackgroundSession`]
+    pub fn spawn(self) -> BackgroundSession {
+        BackgroundSession::new(self)
+    }
+
+    /// 拆分成可以分别放在不同线程中的 [`AudioSender`] 和 [`ResultReceiver`]，实际的收发在后台线程中进行
+    pub fn split(self) -> (AudioSender, ResultReceiver) {
+        self.spawn().split()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use websocket::ws::dataframe::DataFrame as _;
+
+    /// 服务端发送的帧，不加掩码
+    fn frame(finished: bool, opcode: Opcode, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DataFrame::new(finished, opcode, data.to_vec()).write_to(&mut bytes, false).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn frame_split_across_reads() {
+        let text = "x".repeat(300);
+        let bytes = frame(true, Opcode::Text, text.as_bytes());
+        let mut reader = MessageReader::default();
+        // 头部读了一半，然后是正文读了一半
+        for chunk in [&bytes[..1], &bytes[1..3], &bytes[3..100]] {
+            assert_eq!(reader.read_from(&mut &chunk[..]).unwrap(), chunk.len());
+            assert_eq!(reader.next_message().unwrap(), None);
+        }
+        reader.read_from(&mut &bytes[100..]).unwrap();
+        assert_eq!(reader.next_message().unwrap(), Some(OwnedMessage::Text(text)));
+        assert_eq!(reader.next_message().unwrap(), None);
+        assert_eq!(reader.read_from(&mut &[][..]).unwrap(), 0);
+    }
+
+    #[test]
+    fn fragmented_message_with_control_frame() {
+        let mut bytes = frame(false, Opcode::Text, b"Path:turn");
+        bytes.extend(frame(true, Opcode::Ping, b"ping"));
+        bytes.extend(frame(false, Opcode::Continuation, b".e"));
+        bytes.extend(frame(true, Opcode::Continuation, b"nd"));
+        bytes.extend(frame(true, Opcode::Binary, &[1, 2]));
+        let mut reader = MessageReader::default();
+        reader.read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(reader.next_message().unwrap(), Some(OwnedMessage::Ping(b"ping".to_vec())));
+        assert_eq!(reader.next_message().unwrap(), Some(OwnedMessage::Text("Path:turn.end".to_owned())));
+        assert_eq!(reader.next_message().unwrap(), Some(OwnedMessage::Binary(vec![1, 2])));
+        assert_eq!(reader.next_message().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let mut reader = MessageReader::default();
+        reader.read_from(&mut &frame(true, Opcode::Continuation, b"x")[..]).unwrap();
+        assert!(matches!(reader.next_message(), Err(Error::WebSocket(WebSocketError::ProtocolError(_)))));
+
+        let mut reader = MessageReader::default();
+        let mut bytes = frame(false, Opcode::Text, b"a");
+        bytes.extend(frame(true, Opcode::Text, b"b"));
+        reader.read_from(&mut &bytes[..]).unwrap();
+        assert!(matches!(reader.next_message(), Err(Error::WebSocket(WebSocketError::ProtocolError(_)))));
+
+        // 127 表示 8 字节的长度
+        let mut reader = MessageReader::default();
+        let mut bytes = vec![0x81, 127];
+        bytes.extend((MAX_FRAME_SIZE + 1).to_be_bytes());
+        reader.read_from(&mut &bytes[..]).unwrap();
+        assert!(matches!(reader.next_message(), Err(Error::WebSocket(WebSocketError::ProtocolError(_)))));
+    }
+
+    /// 服务端发来的文本消息
+    fn service_message(path: &str, body: &str) -> String {
+        format!("X-RequestId:7a8d0f0bb0c94bd4a1f7e1bd3b5a2e1c\r\nContent-Type:application/json; charset=utf-8\r\nPath:{}\r\n\r\n{}", path, body)
+    }
+
+    #[test]
+    fn parse_events_by_path() {
+        let hypothesis = service_message("speech.hypothesis", r#"{"Text":"what's the","Offset":7300000,"Duration":4700000}"#);
+        assert_eq!(
+            parse_message(hypothesis, OutputFormat::Simple).unwrap(),
+            RecognitionEvent::Hypothesis(SpeechHypothesis {
+                text: "what's the".to_owned(),
+                offset: 7300000,
+                duration: 4700000,
+                primary_language: None,
+            })
+        );
+        let start = service_message("speech.startDetected", r#"{"Offset":7300000}"#);
+        assert_eq!(parse_message(start, OutputFormat::Simple).unwrap(), RecognitionEvent::StartDetected(SpeechStartDetected { offset: 7300000 }));
+        let end = service_message("speech.endDetected", r#"{"Offset":23900000}"#);
+        assert_eq!(parse_message(end, OutputFormat::Simple).unwrap(), RecognitionEvent::EndDetected(SpeechEndDetected { offset: 23900000 }));
+        let turn_start = service_message("turn.start", r#"{"context":{"serviceTag":"7c4f8f0b"}}"#);
+        match parse_message(turn_start, OutputFormat::Simple).unwrap() {
+            RecognitionEvent::TurnStart(turn_start) => assert_eq!(turn_start.context.service_tag, "7c4f8f0b"),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(parse_message(service_message("turn.end", "{}"), OutputFormat::Simple).unwrap(), RecognitionEvent::TurnEnd);
+        // 头部的名字不区分大小写
+        assert_eq!(parse_message("path: turn.end\r\n\r\n", OutputFormat::Simple).unwrap(), RecognitionEvent::TurnEnd);
+    }
+
+    #[test]
+    fn parse_unknown_path() {
+        let message = service_message("speech.fragment", r#"{"Text":"hi"}"#);
+        match parse_message(message, OutputFormat::Simple).unwrap() {
+            RecognitionEvent::Unknown { path, headers, body } => {
+                assert_eq!(path, "speech.fragment");
+                assert_eq!(headers[0], ("X-RequestId".to_owned(), "7a8d0f0bb0c94bd4a1f7e1bd3b5a2e1c".to_owned()));
+                assert_eq!(body, r#"{"Text":"hi"}"#);
             }
+            event => panic!("unexpected event {:?}", event),
         }
+        match parse_message("no headers", OutputFormat::Simple).unwrap() {
+            RecognitionEvent::Unknown { path, .. } => assert_eq!(path, ""),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn parse_invalid_body() {
+        match parse_message(service_message("speech.hypothesis", r#"{"Text":1}"#), OutputFormat::Simple) {
+            Err(Error::Parse { path, .. }) => assert_eq!(path, "speech.hypothesis"),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parse_status() {
+        let status = |body: &str| parse_message(service_message("speech.phrase", body), OutputFormat::Simple).unwrap().recognition_status().cloned();
+        assert_eq!(status(r#"{"RecognitionStatus":"InitialSilenceTimeout","Offset":0,"Duration":0}"#), Some(RecognitionStatus::InitialSilenceTimeout));
+        assert_eq!(status(r#"{"RecognitionStatus":"TooManyRequests","Offset":0,"Duration":0}"#), Some(RecognitionStatus::Other("TooManyRequests".to_owned())));
+    }
+
+    const DETAILED_PHRASE: &str = r#"{"RecognitionStatus":"Success","Offset":7300000,"Duration":16600000,"NBest":[{"Confidence":0.62,"Lexical":"what's the weather like","ITN":"what's the weather like","MaskedITN":"what's the weather like","Display":"What's the weather like?"},{"Confidence":0.91,"Lexical":"what is the weather like in twenty twenty","ITN":"what is the weather like in 2020","MaskedITN":"what is the weather like in 2020","Display":"What is the weather like in 2020?"}]}"#;
+
+    #[test]
+    fn parse_simple_phrase() {
+        let message = service_message("speech.phrase", r#"{"RecognitionStatus":"Success","DisplayText":"What's the weather like?","Offset":7300000,"Duration":16600000}"#);
+        assert_eq!(
+            parse_message(message, OutputFormat::Simple).unwrap(),
+            RecognitionEvent::Phrase(SpeechPhrase {
+                recognition_status: RecognitionStatus::Success,
+                offset: 7300000,
+                duration: 16600000,
+                display_text: "What's the weather like?".to_owned(),
+                primary_language: None,
+            })
+        );
+        let no_match = service_message("speech.phrase", r#"{"RecognitionStatus":"NoMatch","Offset":0,"Duration":0}"#);
+        match parse_message(no_match, OutputFormat::Simple).unwrap() {
+            RecognitionEvent::Phrase(phrase) => {
+                assert_eq!(phrase.recognition_status, RecognitionStatus::NoMatch);
+                assert_eq!(phrase.display_text, "");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn parse_detailed_phrase() {
+        let phrase = match parse_message(service_message("speech.phrase", DETAILED_PHRASE), OutputFormat::Detailed).unwrap() {
+            RecognitionEvent::DetailedPhrase(phrase) => phrase,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(phrase.recognition_status, RecognitionStatus::Success);
+        assert_eq!((phrase.offset, phrase.duration), (7300000, 16600000));
+        assert_eq!(phrase.n_best.len(), 2);
+        let first = &phrase.n_best[0];
+        assert_eq!(first.confidence, 0.62);
+        assert_eq!(first.lexical, "what's the weather like");
+        assert_eq!(first.display, "What's the weather like?");
+        assert!(first.words.is_empty());
+        // 候选结果不一定按置信度排序
+        let best = phrase.best().unwrap();
+        assert_eq!(best.itn, "what is the weather like in 2020");
+        assert_eq!(best.masked_itn, "what is the weather like in 2020");
+
+        let no_match = service_message("speech.phrase", r#"{"RecognitionStatus":"NoMatch","Offset":0,"Duration":0}"#);
+        match parse_message(no_match, OutputFormat::Detailed).unwrap() {
+            RecognitionEvent::DetailedPhrase(phrase) => assert_eq!(phrase.best(), None),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn parse_word_timings() {
+        let body = r#"{"RecognitionStatus":"Success","Offset":7300000,"Duration":4700000,"NBest":[{"Confidence":0.9,"Lexical":"what's the","ITN":"what's the","MaskedITN":"what's the","Display":"What's the","Words":[{"Word":"what's","Offset":7300000,"Duration":2400000},{"Word":"the","Offset":9700000,"Duration":2300000}]}]}"#;
+        let phrase = match parse_message(service_message("speech.phrase", body), OutputFormat::Detailed).unwrap() {
+            RecognitionEvent::DetailedPhrase(phrase) => phrase,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(
+            phrase.best().unwrap().words,
+            [
+                Word {
+                    word: "what's".to_owned(),
+                    offset: Duration::from_millis(730),
+                    duration: Duration::from_millis(240),
+                },
+                Word {
+                    word: "the".to_owned(),
+                    offset: Duration::from_millis(970),
+                    duration: Duration::from_millis(230),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ticks_serde() {
+        let word = Word {
+            word: "hi".to_owned(),
+            offset: Duration::from_nanos(1_234_567_800),
+            duration: Duration::from_millis(5),
+        };
+        let json = serde_json::to_value(&word).unwrap();
+        assert_eq!(json, serde_json::json!({ "Word": "hi", "Offset": 12345678, "Duration": 50000 }));
+        assert_eq!(serde_json::from_value::<Word>(json).unwrap(), word);
+        // 负数视为 0
+        let negative: Word = serde_json::from_str(r#"{"Word":"hi","Offset":-1,"Duration":0}"#).unwrap();
+        assert_eq!(negative.offset, Duration::ZERO);
+        assert_eq!(ticks_to_duration(10_000_000), Duration::from_secs(1));
+        assert_eq!(duration_to_ticks(Duration::from_nanos(199)), 1);
     }
 }
@@ -0,0 +1,321 @@
+//! 把识别结果转换成 SRT 或 WebVTT 字幕。
+//!
+//! 每句话按最大行长度折行，每条字幕最多 `max_lines` 行。一句话分成多条字幕时，
+//! 有词级时间戳就按词的时间切分，否则按字数比例分配时间。
+
+use crate::speech_recognition::{ticks_to_duration, DetailedSpeechPhrase, SpeechPhrase, Word};
+use crate::transcribe::{Transcript, TranscriptPhrase};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleOptions {
+    max_line_length: usize,
+    max_lines: usize,
+    min_duration: Duration,
+    max_duration: Duration,
+}
+
+impl Default for SubtitleOptions {
+    fn default() -> Self {
+        Self {
+            max_line_length: 42,
+            max_lines: 2,
+            min_duration: Duration::from_millis(1000),
+            max_duration: Duration::from_millis(7000),
+        }
+    }
+}
+
+impl SubtitleOptions {
+    /// 每行最多的字符数，默认 42。中文等没有空格的文本按字符折行
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length.max(1);
+        self
+    }
+
+    /// 每条字幕最多的行数，默认 2
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = max_lines.max(1);
+        self
+    }
+
+    /// 每条字幕最短显示时间，默认 1 秒，不会和下一条字幕重叠
+    pub fn min_duration(mut self, min_duration: Duration) -> Self {
+        self.min_duration = min_duration;
+        self
+    }
+
+    /// 每条字幕最长显示时间，默认 7 秒
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+}
+
+/// 生成字幕用的一句话，时间都是在整段音频中的位置
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubtitlePhrase {
+    pub start: Duration,
+    pub duration: Duration,
+    pub text: String,
+    /// 每个词的时间，为空时按字数比例分配时间
+    pub words: Vec<Word>,
+}
+
+/// [`transcribe`](crate::transcribe()) 的结果，Offset 已经调整到整段音频的时间轴上
+impl From<&TranscriptPhrase> for SubtitlePhrase {
+    fn from(phrase: &TranscriptPhrase) -> Self {
+        Self {
+            start: phrase.offset,
+            duration: phrase.duration,
+            text: phrase.text.clone(),
+            words: phrase
+                .words
+                .iter()
+                .map(|word| Word {
+                    word: word.text.clone(),
+                    offset: word.offset,
+                    duration: word.duration,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// 连续识别时每一轮的 Offset 从 0 开始，需要先用 [`RecognitionEvent::shift_offset`](crate::RecognitionEvent::shift_offset)
+/// 调整到同一个时间轴上，或者使用 [`ResilientSession`](crate::ResilientSession)、[`Transcript`]。RecognitionStatus 不是 Success 时没有文本
+impl From<&SpeechPhrase> for SubtitlePhrase {
+    fn from(phrase: &SpeechPhrase) -> Self {
+        Self {
+            start: ticks_to_duration(phrase.offset),
+            duration: ticks_to_duration(phrase.duration),
+            text: if phrase.recognition_status.is_success() { phrase.display_text.clone() } else { String::new() },
+            words: Vec::new(),
+        }
+    }
+}
+
+/// 使用置信度最高的候选结果，注意事项同 [`SpeechPhrase`]
+impl From<&DetailedSpeechPhrase> for SubtitlePhrase {
+    fn from(phrase: &DetailedSpeechPhrase) -> Self {
+        let best = phrase.best().filter(|_| phrase.recognition_status.is_success());
+        Self {
+            start: ticks_to_duration(phrase.offset),
+            duration: ticks_to_duration(phrase.duration),
+            text: best.map(|best| best.display.clone()).unwrap_or_default(),
+            words: best.map(|best| best.words.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+/// 一条字幕
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: Duration,
+    pub end: Duration,
+    pub lines: Vec<String>,
+}
+
+/// 按 `max_line_length` 折行。先按空格分词，超过一行的词按字符切开
+pub fn split_lines(text: &str, max_line_length: usize) -> Vec<String> {
+    let max_line_length = max_line_length.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+    for word in text.split_whitespace() {
+        let chars: Vec<char> = word.chars().collect();
+        for piece in chars.chunks(max_line_length) {
+            let separator = if line.is_empty() { 0 } else { 1 };
+            if line_len + separator + piece.len() > max_line_length {
+                lines.push(std::mem::take(&mut line));
+                line_len = 0;
+            } else if separator == 1 {
+                line.push(' ');
+                line_len += 1;
+            }
+            line.extend(piece);
+            line_len += piece.len();
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// 字幕中第 `start` 到第 `end` 个字符的显示时间。有词级时间戳时把字符位置按比例对应到词，使用这些词的时间
+fn cue_time(phrase: &SubtitlePhrase, start: usize, end: usize, total: usize) -> (Duration, Duration) {
+    let words = &phrase.words;
+    let first = (start * words.len() + total / 2) / total;
+    let last = (end * words.len() + total / 2) / total;
+    if first < last {
+        let end_word = &words[last - 1];
+        return (words[first].offset, end_word.offset + end_word.duration);
+    }
+    let at = |chars: usize| phrase.start + phrase.duration.mul_f64(chars as f64 / total as f64);
+    (at(start), at(end))
+}
+
+/// 没有文本的结果会被跳过
+pub fn cues<P: Into<SubtitlePhrase>>(phrases: impl IntoIterator<Item = P>, options: &SubtitleOptions) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    for phrase in phrases {
+        let phrase: SubtitlePhrase = phrase.into();
+        let lines = split_lines(&phrase.text, options.max_line_length);
+        let total_chars: usize = lines.iter().map(|line| line.chars().count()).sum();
+        if total_chars == 0 {
+            continue;
+        }
+        let mut chars_before = 0;
+        for group in lines.chunks(options.max_lines) {
+            let chars: usize = group.iter().map(|line| line.chars().count()).sum();
+            let (start, end) = cue_time(&phrase, chars_before, chars_before + chars, total_chars);
+            chars_before += chars;
+            cues.push(Cue {
+                start,
+                end,
+                lines: group.to_vec(),
+            });
+        }
+    }
+    // 调整显示时间：不短于 min_duration，不长于 max_duration，不和下一条重叠
+    for i in 0..cues.len() {
+        let next_start = cues.get(i + 1).map(|cue| cue.start);
+        let cue = &mut cues[i];
+        cue.end = cue.end.max(cue.start + options.min_duration).min(cue.start + options.max_duration);
+        if let Some(next_start) = next_start {
+            cue.end = cue.end.min(next_start.max(cue.start));
+        }
+    }
+    cues
+}
+
+/// `separator` 是秒和毫秒之间的分隔符，SRT 是 ','，WebVTT 是 '.'
+fn format_timestamp(time: Duration, separator: char) -> String {
+    let millis = time.as_millis();
+    format!("{:02}:{:02}:{:02}{}{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, separator, millis % 1000)
+}
+
+pub fn cues_to_srt(cues: &[Cue]) -> String {
+    let mut output = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        output.push_str(&format!("{}\n{} --> {}\n", i + 1, format_timestamp(cue.start, ','), format_timestamp(cue.end, ',')));
+        for line in &cue.lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+        output.push('\n');
+    }
+    output
+}
+
+pub fn cues_to_webvtt(cues: &[Cue]) -> String {
+    let mut output = String::from("WEBVTT\n\n");
+    for cue in cues {
+        output.push_str(&format!("{} --> {}\n", format_timestamp(cue.start, '.'), format_timestamp(cue.end, '.')));
+        for line in &cue.lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// [`transcribe`](crate::transcribe()) 等函数的结果，多轮识别的时间已经在同一个时间轴上
+pub fn cues_from_transcript(transcript: &Transcript, options: &SubtitleOptions) -> Vec<Cue> {
+    cues(&transcript.phrases, options)
+}
+
+pub fn to_srt<P: Into<SubtitlePhrase>>(phrases: impl IntoIterator<Item = P>, options: &SubtitleOptions) -> String {
+    cues_to_srt(&cues(phrases, options))
+}
+
+pub fn to_webvtt<P: Into<SubtitlePhrase>>(phrases: impl IntoIterator<Item = P>, options: &SubtitleOptions) -> String {
+    cues_to_webvtt(&cues(phrases, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrase(start_ms: u64, duration_ms: u64, text: &str) -> SubtitlePhrase {
+        SubtitlePhrase {
+            start: Duration::from_millis(start_ms),
+            duration: Duration::from_millis(duration_ms),
+            text: text.to_owned(),
+            words: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn split_lines_by_words_and_chars() {
+        assert_eq!(split_lines("the quick brown fox", 10), ["the quick", "brown fox"]);
+        assert_eq!(split_lines("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(split_lines("今天天气很好", 4), ["今天天气", "很好"]);
+        assert!(split_lines("   ", 10).is_empty());
+    }
+
+    #[test]
+    fn cues_split_time_by_chars() {
+        let options = SubtitleOptions::default().max_line_length(4).max_lines(1).min_duration(Duration::ZERO);
+        let cues = cues([phrase(1000, 4000, "aaaa bbbb")], &options);
+        assert_eq!(cues.len(), 2);
+        assert_eq!((cues[0].start, cues[0].end), (Duration::from_millis(1000), Duration::from_millis(3000)));
+        assert_eq!((cues[1].start, cues[1].end), (Duration::from_millis(3000), Duration::from_millis(5000)));
+        assert_eq!(cues[1].lines, ["bbbb"]);
+    }
+
+    #[test]
+    fn cues_use_word_timings() {
+        let mut phrase = phrase(0, 10_000, "aaaa bbbb");
+        phrase.words = vec![
+            Word {
+                word: "aaaa".to_owned(),
+                offset: Duration::from_millis(500),
+                duration: Duration::from_millis(1000),
+            },
+            Word {
+                word: "bbbb".to_owned(),
+                offset: Duration::from_millis(8000),
+                duration: Duration::from_millis(1000),
+            },
+        ];
+        let options = SubtitleOptions::default().max_line_length(4).max_lines(1).min_duration(Duration::ZERO);
+        let cues = cues([phrase], &options);
+        assert_eq!((cues[0].start, cues[0].end), (Duration::from_millis(500), Duration::from_millis(1500)));
+        assert_eq!((cues[1].start, cues[1].end), (Duration::from_millis(8000), Duration::from_millis(9000)));
+    }
+
+    #[test]
+    fn cues_clamp_duration_without_overlap() {
+        let options = SubtitleOptions::default();
+        let cues = cues([phrase(0, 100, "hi"), phrase(500, 20_000, "there"), phrase(30_000, 0, "")], &options);
+        assert_eq!(cues.len(), 2);
+        // 不短于 1 秒，但不和下一条重叠
+        assert_eq!(cues[0].end, Duration::from_millis(500));
+        // 不长于 7 秒
+        assert_eq!(cues[1].end, Duration::from_millis(7500));
+    }
+
+    #[test]
+    fn skips_unsuccessful_phrases() {
+        let no_match = SpeechPhrase {
+            recognition_status: crate::RecognitionStatus::NoMatch,
+            display_text: "ignored".to_owned(),
+            ..Default::default()
+        };
+        assert!(cues([&no_match], &SubtitleOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn srt_and_webvtt_format() {
+        let cues = [Cue {
+            start: Duration::from_millis(3_723_004),
+            end: Duration::from_millis(3_725_000),
+            lines: vec!["hello".to_owned(), "world".to_owned()],
+        }];
+        assert_eq!(cues_to_srt(&cues), "1\n01:02:03,004 --> 01:02:05,000\nhello\nworld\n\n");
+        assert_eq!(cues_to_webvtt(&cues), "WEBVTT\n\n01:02:03.004 --> 01:02:05.000\nhello\nworld\n\n");
+    }
+}
@@ -0,0 +1,495 @@
+//! 一次调用识别整个 WAV 文件。
+
+use crate::audio::AudioFormat;
+use crate::error::{Error, Result};
+use crate::session_builder::SessionBuilder;
+use crate::speech_recognition::{get_timestamp, ticks_to_duration, RecognitionEvent, RecognitionMode, Session};
+use crate::wav::read_header;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// 每次写入的音频时长
+const CHUNK_DURATION: Duration = Duration::from_millis(100);
+
+/// 音频发送完之后，默认最多等待下一个结果的时间
+const DEFAULT_END_OF_STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// JSONL 格式的版本，写在 [`TranscriptEvent::Start`] 中，字段有不兼容的变化时增加
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct TranscribeOptions {
+    session: SessionBuilder,
+    speed: f64,
+    end_of_stream_timeout: Duration,
+}
+
+impl TranscribeOptions {
+    /// # Arguments
+    /// * `language` - "zh-CN", "en-US"
+    pub fn new(language: &str) -> Self {
+        SessionBuilder::new(language).into()
+    }
+
+    /// 连接的设置。[`RecognitionMode::Interactive`] 会被换成 [`RecognitionMode::Conversation`]，
+    /// 音频格式由 WAV 文件决定，不需要设置 [`SessionBuilder::audio_format`] 和 [`SessionBuilder::wave_header`]。
+    pub fn session(mut self, session: SessionBuilder) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// 发送音频的速度，1.0 是实时，2.0 是两倍速，0 表示不限速。默认 1.0，过快时服务端可能会拒绝。
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// 音频发送完之后，最多等待下一个结果的时间，超时返回 [`Error::Timeout`]。默认 30 秒。
+    /// 每收到一个结果重新计时，不限速时服务端可能还需要较长时间才能处理完所有音频。
+    pub fn end_of_stream_timeout(mut self, timeout: Duration) -> Self {
+        self.end_of_stream_timeout = timeout;
+        self
+    }
+}
+
+impl From<SessionBuilder> for TranscribeOptions {
+    fn from(session: SessionBuilder) -> Self {
+        Self {
+            session,
+            speed: 1.0,
+            end_of_stream_timeout: DEFAULT_END_OF_STREAM_TIMEOUT,
+        }
+    }
+}
+
+mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+/// 置信度较低的其他候选结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alternate {
+    pub text: String,
+    pub confidence: f64,
+}
+
+/// 一个词的时间，需要开启 [`SessionBuilder::word_level_timestamps`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptWord {
+    pub text: String,
+    /// 在整段音频中的开始时间
+    #[serde(rename = "offset_ms", with = "millis")]
+    pub offset: Duration,
+    #[serde(rename = "duration_ms", with = "millis")]
+    pub duration: Duration,
+}
+
+/// 识别出的一句话。时间在 JSON 中以毫秒为单位
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptPhrase {
+    /// 这句话所在的一轮的 X-RequestId
+    pub request_id: String,
+    pub text: String,
+    /// 在整段音频中的开始时间
+    #[serde(rename = "offset_ms", with = "millis")]
+    pub offset: Duration,
+    #[serde(rename = "duration_ms", with = "millis")]
+    pub duration: Duration,
+    /// 只有 [`OutputFormat::Detailed`](crate::OutputFormat::Detailed) 才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    /// 开启语言识别时识别出的语言
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternates: Vec<Alternate>,
+    /// 只有开启了 [`SessionBuilder::word_level_timestamps`] 才有
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<TranscriptWord>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    /// 连接时指定的语言
+    pub language: String,
+    /// 开始识别的时间，RFC 3339 格式
+    pub start_time: String,
+    /// 每一轮的 X-RequestId
+    pub request_ids: Vec<String>,
+    pub phrases: Vec<TranscriptPhrase>,
+}
+
+impl Transcript {
+    /// 所有句子的文本，每句一行
+    pub fn text(&self) -> String {
+        self.phrases.iter().map(|phrase| phrase.text.as_str()).collect::<Vec<_>>().join("\n")
+    }
+
+    fn add_request_id(&mut self, request_id: &str) {
+        if self.request_ids.last().map(String::as_str) != Some(request_id) {
+            self.request_ids.push(request_id.to_owned());
+        }
+    }
+
+    /// 把事件合并到结果中，可以用来从 JSONL 中恢复 Transcript
+    pub fn apply(&mut self, event: &TranscriptEvent) {
+        match event {
+            TranscriptEvent::Start { language, start_time, request_id, .. } => {
+                self.language = language.clone();
+                self.start_time = start_time.clone();
+                self.add_request_id(request_id);
+            }
+            TranscriptEvent::Hypothesis { request_id, .. } => self.add_request_id(request_id),
+            TranscriptEvent::Phrase(phrase) => {
+                self.add_request_id(&phrase.request_id);
+                self.phrases.push(phrase.clone());
+            }
+        }
+    }
+}
+
+/// JSONL 中的一行，`type` 字段区分种类
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    /// 连接成功，第一行
+    Start {
+        /// [`TRANSCRIPT_VERSION`]
+        #[serde(default)]
+        version: u32,
+        language: String,
+        start_time: String,
+        request_id: String,
+    },
+    /// 中间结果
+    Hypothesis {
+        request_id: String,
+        text: String,
+        #[serde(rename = "offset_ms", with = "millis")]
+        offset: Duration,
+        #[serde(rename = "duration_ms", with = "millis")]
+        duration: Duration,
+    },
+    Phrase(TranscriptPhrase),
+}
+
+impl TranscriptEvent {
+    /// 连接成功时的第一个事件，开始时间是当前时间
+    pub fn start(language: &str, request_id: &str) -> Self {
+        TranscriptEvent::Start {
+            version: TRANSCRIPT_VERSION,
+            language: language.to_owned(),
+            start_time: get_timestamp(),
+            request_id: request_id.to_owned(),
+        }
+    }
+
+    /// 把识别结果转换成事件，用于把 [`Session`] 等收到的结果写入 [`JsonlWriter`]。
+    /// 只转换中间结果和成功的最终结果，其他事件和没有文本的结果返回 None。
+    ///
+    /// # Arguments
+    /// * `request_id` - 这个结果所在的一轮的 X-RequestId
+    /// * `turn_offset` - 这一轮之前的音频时长，加到所有的 Offset 上。连续识别时每一轮的 Offset 从 0 开始，
+    ///   可以用 [`Session::audio_bytes_before_turn`] 换算；[`ResilientSession`](crate::ResilientSession) 的结果已经调整过，传入 0
+    pub fn from_event(event: &RecognitionEvent, request_id: &str, turn_offset: Duration) -> Option<Self> {
+        let request_id = request_id.to_owned();
+        let phrase = match event {
+            RecognitionEvent::Hypothesis(hypothesis) => {
+                return Some(TranscriptEvent::Hypothesis {
+                    request_id,
+                    text: hypothesis.text.clone(),
+                    offset: turn_offset + ticks_to_duration(hypothesis.offset),
+                    duration: ticks_to_duration(hypothesis.duration),
+                });
+            }
+            RecognitionEvent::Phrase(phrase) if phrase.recognition_status.is_success() => TranscriptPhrase {
+                request_id,
+                text: phrase.display_text.clone(),
+                offset: turn_offset + ticks_to_duration(phrase.offset),
+                duration: ticks_to_duration(phrase.duration),
+                confidence: None,
+                language: phrase.primary_language.as_ref().map(|language| language.language.clone()),
+                alternates: Vec::new(),
+                words: Vec::new(),
+            },
+            RecognitionEvent::DetailedPhrase(phrase) if phrase.recognition_status.is_success() => {
+                let best = phrase.best()?;
+                TranscriptPhrase {
+                    request_id,
+                    text: best.display.clone(),
+                    offset: turn_offset + ticks_to_duration(phrase.offset),
+                    duration: ticks_to_duration(phrase.duration),
+                    confidence: Some(best.confidence),
+                    language: phrase.primary_language.as_ref().map(|language| language.language.clone()),
+                    alternates: phrase
+                        .n_best
+                        .iter()
+                        .filter(|entry| *entry != best)
+                        .map(|entry| Alternate {
+                            text: entry.display.clone(),
+                            confidence: entry.confidence,
+                        })
+                        .collect(),
+                    words: best
+                        .words
+                        .iter()
+                        .map(|word| TranscriptWord {
+                            text: word.word.clone(),
+                            offset: turn_offset + word.offset,
+                            duration: word.duration,
+                        })
+                        .collect(),
+                }
+            }
+            _ => return None,
+        };
+        if phrase.text.is_empty() {
+            return None;
+        }
+        Some(TranscriptEvent::Phrase(phrase))
+    }
+}
+
+/// 每个事件写一行 JSON，写完立即 flush，适合一边识别一边交给下游处理
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, event: &TranscriptEvent) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// See [`transcribe`]
+///
+/// # Returns
+/// * Err(Error::Io), when the file can't be opened or read
+/// * Others same as [`transcribe`]
+pub fn transcribe_file(path: impl AsRef<Path>, options: &TranscribeOptions) -> Result<Transcript> {
+    let file = File::open(path).map_err(Error::Io)?;
+    transcribe(BufReader::new(file), options)
+}
+
+/// 读取 WAV 音频，按 `options` 中的速度发送，等所有结果返回之后关闭会话
+///
+/// # Returns
+/// * Err(Error::InvalidWav), when the input is not a WAV file, or the sample format is not supported
+/// * Others same as [`transcribe_stream`]
+pub fn transcribe(reader: impl Read, options: &TranscribeOptions) -> Result<Transcript> {
+    let (audio_format, reader) = wav_stream(reader)?;
+    transcribe_stream(reader, audio_format, options, |_| {})
+}
+
+/// 读取 WAV 头部，返回音频格式和只读取音频数据的 reader，可以直接交给 [`transcribe_stream`]。
+/// 长度未知的音频流一直读到结尾。
+///
+/// # Returns
+/// * Err(Error::InvalidWav), when the input is not a WAV file, or the sample format is not supported
+/// * Err(Error::Io), when reading fails
+pub fn wav_stream<R: Read>(mut reader: R) -> Result<(AudioFormat, io::Take<R>)> {
+    let header = read_header(&mut reader)?;
+    let audio_format = header.format.audio_format().ok_or_else(|| Error::InvalidWav(format!("unsupported sample format {:?}", header.format)))?;
+    let data_len = header.data_len.map_or(u64::MAX, u64::from);
+    Ok((audio_format, reader.take(data_len)))
+}
+
+/// 识别没有 WAV 头部的 PCM 音频，连接成功和每收到一个识别结果都会调用 `on_event`，可以用来显示中间结果或者写入 [`JsonlWriter`]
+///
+/// # Returns
+/// * Err(Error::InvalidWav), when the sample rate or the number of channels is 0
+/// * Err(Error::Io), when reading fails
+/// * Err(Error::Recognition), when a phrase ends with [`RecognitionStatus::Error`](crate::RecognitionStatus::Error) or an unknown status.
+///   NoMatch and timeouts only produce no text
+/// * Err(Error::Timeout), when no result arrives within [`TranscribeOptions::end_of_stream_timeout`] after all audio has been sent
+/// * Others same as [`SessionBuilder::connect`] and [`Session::recv_message`]
+pub fn transcribe_stream(mut reader: impl Read, audio_format: AudioFormat, options: &TranscribeOptions, mut on_event: impl FnMut(&TranscriptEvent)) -> Result<Transcript> {
+    if audio_format.sample_rate == 0 || audio_format.channels == 0 {
+        return Err(Error::InvalidWav(format!("unsupported audio format {:?}", audio_format)));
+    }
+    let mut builder = options.session.clone().audio_format(audio_format);
+    if builder.recognition_mode == RecognitionMode::Interactive {
+        builder = builder.recognition_mode(RecognitionMode::Conversation);
+    }
+    let mut session = builder.connect()?;
+    let mut transcript = Transcript::default();
+    let start = TranscriptEvent::start(&builder.language, session.request_id());
+    transcript.apply(&start);
+    on_event(&start);
+    let mut handle_event = |transcript: &mut Transcript, session: &Session, event: RecognitionEvent| {
+        if let Some(status) = event.recognition_status().filter(|status| status.is_error()) {
+            return Err(Error::Recognition { status: status.clone() });
+        }
+        if let Some(event) = transcript_event(session, event) {
+            transcript.apply(&event);
+            on_event(&event);
+        }
+        Ok(())
+    };
+
+    let block_align = audio_format.block_align();
+    let frames_per_chunk = (audio_format.sample_rate as u128 * CHUNK_DURATION.as_millis() / 1000).max(1) as usize;
+    let mut chunk = vec![0u8; frames_per_chunk * block_align];
+    let mut sent_frames = 0u64;
+    let start = Instant::now();
+    loop {
+        let len = read_full(&mut reader, &mut chunk)?;
+        if len == 0 {
+            break;
+        }
+        session.write(&chunk[..len])?;
+        sent_frames += (len / block_align) as u64;
+        // 按音频的时长控制发送速度，等待的同时接收结果
+        if options.speed > 0.0 {
+            let audio_duration = Duration::from_secs_f64(sent_frames as f64 / audio_format.sample_rate as f64 / options.speed);
+            while let Some(remaining) = audio_duration.checked_sub(start.elapsed()).filter(|d| !d.is_zero()) {
+                if let Some(event) = session.recv_message_timeout(remaining)? {
+                    handle_event(&mut transcript, &session, event)?;
+                }
+            }
+        }
+        while let Some(event) = session.try_recv_message()? {
+            handle_event(&mut transcript, &session, event)?;
+        }
+        if len < chunk.len() {
+            break;
+        }
+    }
+
+    session.end_audio()?;
+    while !session.is_closed() {
+        let event = session.recv_message_timeout(options.end_of_stream_timeout)?.ok_or(Error::Timeout)?;
+        handle_event(&mut transcript, &session, event)?;
+    }
+    Ok(transcript)
+}
+
+/// 读满 `buf`，除非到了结尾
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+    Ok(len)
+}
+
+/// 把识别结果转换成 [`TranscriptEvent`]，Offset 加上之前各轮音频的时长
+fn transcript_event(session: &Session, event: RecognitionEvent) -> Option<TranscriptEvent> {
+    // 发送的音频是 16 kHz 16 位单声道
+    let target = AudioFormat::TARGET;
+    let bytes_per_second = target.sample_rate as u64 * target.block_align() as u64;
+    let turn_offset = Duration::from_secs_f64(session.audio_bytes_before_turn() as f64 / bytes_per_second as f64);
+    TranscriptEvent::from_event(&event, session.request_id(), turn_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// JSONL 的字段名是对外的格式，不能随意改变
+    #[test]
+    fn jsonl_schema() {
+        let phrase = TranscriptPhrase {
+            request_id: "R1".to_owned(),
+            text: "hello".to_owned(),
+            offset: Duration::from_millis(1500),
+            duration: Duration::from_millis(800),
+            confidence: Some(0.9),
+            language: Some("en-US".to_owned()),
+            alternates: vec![Alternate {
+                text: "yellow".to_owned(),
+                confidence: 0.1,
+            }],
+            words: vec![TranscriptWord {
+                text: "hello".to_owned(),
+                offset: Duration::from_millis(1500),
+                duration: Duration::from_millis(800),
+            }],
+        };
+        let events = [
+            TranscriptEvent::Start {
+                version: TRANSCRIPT_VERSION,
+                language: "en-US".to_owned(),
+                start_time: "2024-01-01T00:00:00Z".to_owned(),
+                request_id: "R1".to_owned(),
+            },
+            TranscriptEvent::Hypothesis {
+                request_id: "R1".to_owned(),
+                text: "hel".to_owned(),
+                offset: Duration::from_millis(1500),
+                duration: Duration::from_millis(300),
+            },
+            TranscriptEvent::Phrase(phrase),
+        ];
+        let mut writer = JsonlWriter::new(Vec::new());
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(
+            lines,
+            [
+                json!({ "type": "start", "version": 1, "language": "en-US", "start_time": "2024-01-01T00:00:00Z", "request_id": "R1" }),
+                json!({ "type": "hypothesis", "request_id": "R1", "text": "hel", "offset_ms": 1500, "duration_ms": 300 }),
+                json!({
+                    "type": "phrase",
+                    "request_id": "R1",
+                    "text": "hello",
+                    "offset_ms": 1500,
+                    "duration_ms": 800,
+                    "confidence": 0.9,
+                    "language": "en-US",
+                    "alternates": [{ "text": "yellow", "confidence": 0.1 }],
+                    "words": [{ "text": "hello", "offset_ms": 1500, "duration_ms": 800 }],
+                }),
+            ]
+        );
+        let parsed: Vec<TranscriptEvent> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(parsed, events);
+    }
+
+    #[test]
+    fn from_event_rebases_offsets() {
+        let event = RecognitionEvent::Phrase(crate::speech_recognition::SpeechPhrase {
+            display_text: "hello".to_owned(),
+            offset: 5_000_000,
+            duration: 10_000_000,
+            ..Default::default()
+        });
+        let Some(TranscriptEvent::Phrase(phrase)) = TranscriptEvent::from_event(&event, "R2", Duration::from_secs(10)) else {
+            panic!("expected a phrase");
+        };
+        assert_eq!(phrase.request_id, "R2");
+        assert_eq!(phrase.offset, Duration::from_millis(10_500));
+        assert_eq!(phrase.duration, Duration::from_secs(1));
+    }
+}
@@ -27,6 +27,7 @@ pub fn short_time_energy<'a>(samples: impl Iterator<Item=&'a f32>) -> f32 {
 }
 
 /// 声音活动检测。双阈值。
+#[allow(clippy::too_many_arguments)]
 pub fn voice_activity_detection(is_prev_frame_active: bool, zcr: f32, ste: f32, zcr_threshold_low: f32, zcr_threshold_high: f32, ste_min: f32, ste_max: f32, ste_threshold: f32) -> bool {
     if is_prev_frame_active {
         zcr < zcr_threshold_high || ste > ste_min + (ste_max - ste_min) * ste_threshold
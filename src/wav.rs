@@ -0,0 +1,350 @@
+//! WAV 头部的生成和解析。
+//!
+//! 支持 WAVE_FORMAT_PCM、WAVE_FORMAT_IEEE_FLOAT 和 WAVE_FORMAT_EXTENSIBLE。
+
+use crate::audio::{AudioFormat, SampleType};
+use crate::error::{Error, Result};
+use std::io::{self, Read};
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// fmt 块的最大长度：WAVEFORMATEX 的 18 字节加上最多 64 字节的扩展数据，WAVEFORMATEXTENSIBLE 只需要 40 字节
+const MAX_FMT_CHUNK_LEN: u32 = 18 + 64;
+
+/// KSDATAFORMAT_SUBTYPE_PCM {00000001-0000-0010-8000-00AA00389B71}
+pub const KSDATAFORMAT_SUBTYPE_PCM: [u8; 16] = [0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+/// KSDATAFORMAT_SUBTYPE_IEEE_FLOAT {00000003-0000-0010-8000-00AA00389B71}
+pub const KSDATAFORMAT_SUBTYPE_IEEE_FLOAT: [u8; 16] = [0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaveFormatTag {
+    Pcm,
+    IeeeFloat,
+    /// WAVEFORMATEXTENSIBLE
+    Extensible {
+        /// 有效位数，可以小于 bits_per_sample，例如 24 位有效数据放在 32 位容器中
+        valid_bits_per_sample: u16,
+        /// 每个声道对应的扬声器位置，例如 SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT = 0x3
+        channel_mask: u32,
+        /// 实际的格式，例如 [`KSDATAFORMAT_SUBTYPE_PCM`]，GUID 按内存中的字节顺序
+        sub_format: [u8; 16],
+    },
+}
+
+/// WAV 文件 fmt 块的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveFormat {
+    pub tag: WaveFormatTag,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+impl WaveFormat {
+    pub fn pcm(sample_rate: u32, channels: u16, bits_per_sample: u16) -> Self {
+        Self {
+            tag: WaveFormatTag::Pcm,
+            channels,
+            sample_rate,
+            bits_per_sample,
+        }
+    }
+
+    /// 32 位浮点
+    pub fn ieee_float(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            tag: WaveFormatTag::IeeeFloat,
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+        }
+    }
+
+    /// 超出 u16 范围时为 u16::MAX，这样的格式无法写入 fmt 块
+    pub fn block_align(&self) -> u16 {
+        self.channels.saturating_mul(self.bits_per_sample / 8)
+    }
+
+    pub fn avg_bytes_per_sec(&self) -> u32 {
+        self.sample_rate.saturating_mul(self.block_align() as u32)
+    }
+
+    /// 对应的 wFormatTag，扩展格式返回 [`WAVE_FORMAT_EXTENSIBLE`]
+    pub fn format_tag(&self) -> u16 {
+        match self.tag {
+            WaveFormatTag::Pcm => WAVE_FORMAT_PCM,
+            WaveFormatTag::IeeeFloat => WAVE_FORMAT_IEEE_FLOAT,
+            WaveFormatTag::Extensible { .. } => WAVE_FORMAT_EXTENSIBLE,
+        }
+    }
+
+    /// 可以交给 [`AudioConverter`](crate::AudioConverter) 转换的格式，不支持的格式，或者采样率、声道数为 0 时返回 None
+    pub fn audio_format(&self) -> Option<AudioFormat> {
+        if self.sample_rate == 0 || self.channels == 0 {
+            return None;
+        }
+        let float = match self.tag {
+            WaveFormatTag::Pcm => false,
+            WaveFormatTag::IeeeFloat => true,
+            WaveFormatTag::Extensible { sub_format, .. } if sub_format == KSDATAFORMAT_SUBTYPE_PCM => false,
+            WaveFormatTag::Extensible { sub_format, .. } if sub_format == KSDATAFORMAT_SUBTYPE_IEEE_FLOAT => true,
+            WaveFormatTag::Extensible { .. } => return None,
+        };
+        let sample_type = match (float, self.bits_per_sample) {
+            (false, 16) => SampleType::I16,
+            (false, 24) => SampleType::I24,
+            (false, 32) => SampleType::I32,
+            (true, 32) => SampleType::F32,
+            _ => return None,
+        };
+        Some(AudioFormat::new(self.sample_rate, self.channels, sample_type))
+    }
+
+    /// fmt 块的内容，不含块 ID 和长度
+    pub fn to_fmt_chunk(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(40);
+        buf.extend_from_slice(&self.format_tag().to_le_bytes());
+        buf.extend_from_slice(&self.channels.to_le_bytes());
+        buf.extend_from_slice(&self.sample_rate.to_le_bytes());
+        buf.extend_from_slice(&self.avg_bytes_per_sec().to_le_bytes());
+        buf.extend_from_slice(&self.block_align().to_le_bytes());
+        buf.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+        match self.tag {
+            WaveFormatTag::Pcm => {}
+            WaveFormatTag::IeeeFloat => buf.extend_from_slice(&0u16.to_le_bytes()),
+            WaveFormatTag::Extensible {
+                valid_bits_per_sample,
+                channel_mask,
+                sub_format,
+            } => {
+                buf.extend_from_slice(&22u16.to_le_bytes());
+                buf.extend_from_slice(&valid_bits_per_sample.to_le_bytes());
+                buf.extend_from_slice(&channel_mask.to_le_bytes());
+                buf.extend_from_slice(&sub_format);
+            }
+        }
+        buf
+    }
+
+    /// 解析 fmt 块的内容，例如 Windows 的 WAVEFORMATEX 或 WAVEFORMATEXTENSIBLE 结构体的字节
+    ///
+    /// # Returns
+    /// * Err(Error::InvalidWav), when the chunk is too short or the format tag is not supported
+    pub fn from_fmt_chunk(data: &[u8]) -> Result<Self> {
+        if data.len() < 16 {
+            return Err(Error::InvalidWav("fmt chunk is too short".to_owned()));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let tag = match u16_at(0) {
+            WAVE_FORMAT_PCM => WaveFormatTag::Pcm,
+            WAVE_FORMAT_IEEE_FLOAT => WaveFormatTag::IeeeFloat,
+            WAVE_FORMAT_EXTENSIBLE if data.len() >= 40 => WaveFormatTag::Extensible {
+                valid_bits_per_sample: u16_at(18),
+                channel_mask: u32_at(20),
+                sub_format: data[24..40].try_into().unwrap(),
+            },
+            WAVE_FORMAT_EXTENSIBLE => return Err(Error::InvalidWav("WAVE_FORMAT_EXTENSIBLE fmt chunk is too short".to_owned())),
+            tag => return Err(Error::InvalidWav(format!("unsupported format tag 0x{:04X}", tag))),
+        };
+        Ok(Self {
+            tag,
+            channels: u16_at(2),
+            sample_rate: u32_at(4),
+            bits_per_sample: u16_at(14),
+        })
+    }
+
+    /// 完整的 WAV 头部，之后紧接着是音频数据
+    ///
+    /// # Arguments
+    /// * `data_len` - 音频数据的字节数，None 表示长度未知的音频流，RIFF 和 data 块的长度都写为 0，和 JavaScript Speech SDK 相同
+    pub fn header(&self, data_len: Option<u32>) -> Vec<u8> {
+        riff_header(&self.to_fmt_chunk(), data_len)
+    }
+}
+
+impl From<AudioFormat> for WaveFormat {
+    fn from(format: AudioFormat) -> Self {
+        match format.sample_type {
+            SampleType::F32 => WaveFormat::ieee_float(format.sample_rate, format.channels),
+            sample_type => WaveFormat::pcm(format.sample_rate, format.channels, (sample_type.bytes_per_sample() * 8) as u16),
+        }
+    }
+}
+
+/// 用任意的 fmt 块内容生成 WAV 头部
+pub(crate) fn riff_header(fmt_chunk: &[u8], data_len: Option<u32>) -> Vec<u8> {
+    let riff_len = data_len.map_or(0, |data_len| (4 + 8 + fmt_chunk.len() as u32 + 8).saturating_add(data_len));
+    let mut buf = Vec::with_capacity(28 + fmt_chunk.len());
+    buf.extend_from_slice(b"RIFF");
+    buf.extend_from_slice(&riff_len.to_le_bytes());
+    buf.extend_from_slice(b"WAVE");
+    buf.extend_from_slice(b"fmt ");
+    buf.extend_from_slice(&(fmt_chunk.len() as u32).to_le_bytes());
+    buf.extend_from_slice(fmt_chunk);
+    buf.extend_from_slice(b"data");
+    buf.extend_from_slice(&data_len.unwrap_or(0).to_le_bytes());
+    buf
+}
+
+/// [`read_header`] 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveHeader {
+    pub format: WaveFormat,
+    /// data 块的长度，长度为 0 或 0xFFFFFFFF 的音频流时为 None
+    pub data_len: Option<u32>,
+}
+
+/// 读取 WAV 头部，跳过 fmt 和 data 之外的块，返回之后 `reader` 正好位于音频数据的开头
+///
+/// # Returns
+/// * Err(Error::InvalidWav), when the data is not a WAV file, ends before the data chunk, has no fmt chunk before the data chunk, or the fmt chunk is too long
+/// * Err(Error::Io), when reading fails
+pub fn read_header(mut reader: impl Read) -> Result<WaveHeader> {
+    let mut riff = [0u8; 12];
+    read_exact(&mut reader, &mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(Error::InvalidWav("missing RIFF/WAVE signature".to_owned()));
+    }
+    let mut format = None;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        read_exact(&mut reader, &mut chunk_header)?;
+        let chunk_len = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]);
+        match &chunk_header[0..4] {
+            b"data" => {
+                let format = format.ok_or_else(|| Error::InvalidWav("data chunk before fmt chunk".to_owned()))?;
+                return Ok(WaveHeader {
+                    format,
+                    data_len: if chunk_len == 0 || chunk_len == u32::MAX { None } else { Some(chunk_len) },
+                });
+            }
+            b"fmt " => {
+                if chunk_len > MAX_FMT_CHUNK_LEN {
+                    return Err(Error::InvalidWav(format!("fmt chunk is too long ({} bytes)", chunk_len)));
+                }
+                let mut chunk = vec![0u8; chunk_len as usize + (chunk_len & 1) as usize];
+                read_exact(&mut reader, &mut chunk)?;
+                format = Some(WaveFormat::from_fmt_chunk(&chunk[..chunk_len as usize])?);
+            }
+            _ => {
+                // 块的长度是奇数时有一个填充字节
+                let skip = chunk_len as u64 + (chunk_len & 1) as u64;
+                if io::copy(&mut (&mut reader).take(skip), &mut io::sink()).map_err(Error::Io)? != skip {
+                    return Err(truncated());
+                }
+            }
+        }
+    }
+}
+
+/// 头部还没读完数据就结束了，说明不是 WAV 文件或者文件不完整，不是 IO 错误
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| if e.kind() == io::ErrorKind::UnexpectedEof { truncated() } else { Error::Io(e) })
+}
+
+fn truncated() -> Error {
+    Error::InvalidWav("unexpected end of data before the data chunk".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fmt_chunk_round_trip() {
+        let formats = [
+            WaveFormat::pcm(16000, 1, 16),
+            WaveFormat::pcm(44100, 2, 24),
+            WaveFormat::ieee_float(48000, 2),
+            WaveFormat {
+                tag: WaveFormatTag::Extensible {
+                    valid_bits_per_sample: 24,
+                    channel_mask: 0x3,
+                    sub_format: KSDATAFORMAT_SUBTYPE_PCM,
+                },
+                channels: 2,
+                sample_rate: 96000,
+                bits_per_sample: 32,
+            },
+        ];
+        for format in formats {
+            assert_eq!(WaveFormat::from_fmt_chunk(&format.to_fmt_chunk()).unwrap(), format);
+            let header = read_header(&format.header(Some(1000))[..]).unwrap();
+            assert_eq!(header, WaveHeader { format, data_len: Some(1000) });
+        }
+    }
+
+    #[test]
+    fn header_layout() {
+        let header = WaveFormat::pcm(16000, 1, 16).header(None);
+        assert_eq!(header.len(), 44);
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(&header[4..8], &[0, 0, 0, 0]);
+        assert_eq!(&header[36..40], b"data");
+        // avg_bytes_per_sec 和 block_align
+        assert_eq!(&header[28..32], &32000u32.to_le_bytes());
+        assert_eq!(&header[32..34], &2u16.to_le_bytes());
+        assert_eq!(read_header(&header[..]).unwrap().data_len, None);
+    }
+
+    #[test]
+    fn skips_unknown_chunks_with_padding() {
+        let format = WaveFormat::pcm(8000, 1, 16);
+        let fmt_chunk = format.to_fmt_chunk();
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+        data.extend_from_slice(b"LIST");
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"abc\0");
+        data.extend_from_slice(b"fmt ");
+        data.extend_from_slice(&(fmt_chunk.len() as u32).to_le_bytes());
+        data.extend_from_slice(&fmt_chunk);
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        let mut reader = &data[..];
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!(header, WaveHeader { format, data_len: Some(4) });
+        assert_eq!(reader, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(matches!(read_header(&b"RIFX\0\0\0\0WAVE"[..]), Err(Error::InvalidWav(_))));
+        let mut data_before_fmt = b"RIFF\0\0\0\0WAVE".to_vec();
+        data_before_fmt.extend_from_slice(b"data\0\0\0\0");
+        assert!(matches!(read_header(&data_before_fmt[..]), Err(Error::InvalidWav(_))));
+        let mut huge_fmt = b"RIFF\0\0\0\0WAVE".to_vec();
+        huge_fmt.extend_from_slice(b"fmt ");
+        huge_fmt.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read_header(&huge_fmt[..]), Err(Error::InvalidWav(_))));
+    }
+
+    #[test]
+    fn truncated_input_is_invalid_wav() {
+        assert!(matches!(read_header(&b"notawav\n"[..]), Err(Error::InvalidWav(_))));
+        assert!(matches!(read_header(&b""[..]), Err(Error::InvalidWav(_))));
+        let header = WaveFormat::pcm(16000, 1, 16).header(None);
+        // 截断在 fmt 块、块头和未知块中间
+        for len in [20, 40, 12 + 8 + 16 + 4] {
+            assert!(matches!(read_header(&header[..len]), Err(Error::InvalidWav(_))), "{}", len);
+        }
+        let mut unknown = header[..12].to_vec();
+        unknown.extend_from_slice(b"LIST\x10\0\0\0abc");
+        assert!(matches!(read_header(&unknown[..]), Err(Error::InvalidWav(_))));
+    }
+
+    #[test]
+    fn audio_format() {
+        assert_eq!(WaveFormat::pcm(16000, 1, 16).audio_format(), Some(AudioFormat::TARGET));
+        assert_eq!(WaveFormat::ieee_float(48000, 2).audio_format(), Some(AudioFormat::new(48000, 2, SampleType::F32)));
+        assert_eq!(WaveFormat::pcm(16000, 1, 8).audio_format(), None);
+        assert_eq!(WaveFormat::pcm(0, 1, 16).audio_format(), None);
+        assert_eq!(WaveFormat::pcm(16000, 0, 16).audio_format(), None);
+        assert_eq!(WaveFormat::pcm(16000, u16::MAX, 32).block_align(), u16::MAX);
+    }
+}
@@ -0,0 +1,131 @@
+use bing_stt::mock::{MockServer, MockStep};
+use bing_stt::{AsyncSession, AudioFormat, Error, RecognitionEvent, RecognitionMode, SessionBuilder};
+use futures_util::{SinkExt, StreamExt};
+
+/// 100 毫秒的 16 kHz 16 位单声道静音
+const CHUNK: [u8; 3200] = [0; 3200];
+
+fn builder(server: &MockServer) -> SessionBuilder {
+    SessionBuilder::new("en-US").endpoint(server.url().as_str())
+}
+
+/// 读取所有的事件，直到 Stream 结束
+async fn collect(session: &mut AsyncSession) -> Vec<RecognitionEvent> {
+    let mut events = Vec::new();
+    while let Some(event) = session.next().await {
+        events.push(event.unwrap());
+    }
+    events
+}
+
+fn texts(events: &[RecognitionEvent]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|event| match event {
+            RecognitionEvent::Hypothesis(hypothesis) => hypothesis.text.as_str(),
+            RecognitionEvent::Phrase(phrase) => phrase.display_text.as_str(),
+            RecognitionEvent::TurnStart(_) => "turn.start",
+            RecognitionEvent::TurnEnd => "turn.end",
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn interactive_stream_ends_after_turn_end() {
+    let server = MockServer::start(vec![
+        MockStep::turn_start(0),
+        MockStep::hypothesis(CHUNK.len(), "hello", 0, 1_000_000),
+        MockStep::phrase(2 * CHUNK.len(), "Hello world.", 0, 2_000_000),
+        MockStep::turn_end(2 * CHUNK.len()),
+    ])
+    .unwrap();
+    let mut session = builder(&server).connect_async().await.unwrap();
+    session.send(CHUNK.to_vec()).await.unwrap();
+    session.send(CHUNK.to_vec()).await.unwrap();
+    session.flush().await.unwrap();
+    session.end_audio().await.unwrap();
+    assert!(matches!(session.send(CHUNK.to_vec()).await, Err(Error::Closed)));
+
+    let events = collect(&mut session).await;
+    assert_eq!(texts(&events), ["turn.start", "hello", "Hello world.", "turn.end"]);
+    assert!(session.is_closed());
+}
+
+#[tokio::test]
+async fn conversation_sends_context_before_next_turn() {
+    let server = MockServer::start(vec![
+        MockStep::phrase(CHUNK.len(), "one", 0, 1_000_000),
+        MockStep::turn_end(CHUNK.len()),
+        MockStep::phrase(3 * CHUNK.len(), "two", 0, 2_000_000),
+        MockStep::turn_end(3 * CHUNK.len()),
+    ])
+    .unwrap();
+    let mut session = builder(&server).recognition_mode(RecognitionMode::Conversation).connect_async().await.unwrap();
+    let first_request_id = session.request_id().to_owned();
+    session.send(CHUNK.to_vec()).await.unwrap();
+    assert_eq!(texts(&[session.next().await.unwrap().unwrap(), session.next().await.unwrap().unwrap()]), ["one", "turn.end"]);
+    assert!(!session.is_closed());
+    assert_ne!(session.request_id(), first_request_id);
+    assert_eq!(session.audio_bytes_before_turn(), CHUNK.len() as u64);
+
+    session.send(CHUNK.to_vec()).await.unwrap();
+    session.send(CHUNK.to_vec()).await.unwrap();
+    session.end_audio().await.unwrap();
+    assert_eq!(texts(&collect(&mut session).await), ["two", "turn.end"]);
+
+    let connections = server.connections();
+    let messages = &connections[0].messages;
+    assert!(connections[0].errors.is_empty(), "{:?}", connections[0].errors);
+    let paths: Vec<&str> = messages.iter().map(|message| message.path.as_str()).filter(|&path| path != "audio").collect();
+    assert_eq!(paths, ["speech.config", "speech.context", "speech.context"]);
+    let second_turn = messages.iter().rposition(|message| message.path == "speech.context").unwrap();
+    assert_eq!(messages[second_turn + 1].path, "audio");
+    assert!(messages.last().unwrap().body.is_empty());
+}
+
+/// 音频在第一个 turn.end 之前就全部发送完了，Stream 要重新发送没有识别的音频，并再次结束音频
+#[tokio::test]
+async fn conversation_replays_audio_after_turn_boundary() {
+    let server = MockServer::start(vec![
+        MockStep::phrase(CHUNK.len(), "one", 0, 1_000_000),
+        MockStep::turn_end(CHUNK.len()),
+        MockStep::phrase(2 * CHUNK.len(), "two", 0, 2_000_000),
+        MockStep::turn_end(2 * CHUNK.len()),
+    ])
+    .unwrap();
+    let mut session = builder(&server)
+        .audio_format(AudioFormat::TARGET)
+        .recognition_mode(RecognitionMode::Conversation)
+        .flush_size(CHUNK.len())
+        .connect_async()
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        session.send(CHUNK.to_vec()).await.unwrap();
+    }
+    session.end_audio().await.unwrap();
+    assert_eq!(texts(&collect(&mut session).await), ["one", "turn.end", "two", "turn.end"]);
+    assert_eq!(session.audio_bytes_before_turn(), CHUNK.len() as u64);
+
+    let connections = server.connections();
+    let messages = &connections[0].messages;
+    let second_turn = messages.iter().rposition(|message| message.path == "speech.context").unwrap();
+    let replayed: usize = messages[second_turn..].iter().filter(|message| message.path == "audio").map(|message| message.body.len()).sum();
+    assert_eq!(replayed, 44 + 2 * CHUNK.len());
+}
+
+#[tokio::test]
+async fn close_sends_pending_audio() {
+    let server = MockServer::start(Vec::new()).unwrap();
+    let mut session = builder(&server).connect_async().await.unwrap();
+    session.send(CHUNK[..100].to_vec()).await.unwrap();
+    session.close().await.unwrap();
+    assert!(session.is_closed());
+    assert!(matches!(session.send(CHUNK.to_vec()).await, Err(Error::Closed)));
+    assert!(session.next().await.is_none());
+
+    let connections = server.connections();
+    let audio: Vec<usize> = connections[0].messages.iter().filter(|message| message.path == "audio").map(|message| message.body.len()).collect();
+    assert_eq!(audio, [100]);
+}
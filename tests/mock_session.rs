@@ -0,0 +1,291 @@
+use bing_stt::mock::{MockConnection, MockServer, MockStep};
+use bing_stt::{AudioFormat, CancellationReason, Error, RecognitionEvent, RecognitionMode, RecognitionStatus, ReconnectPolicy, ResilientSession, SampleType, SessionBuilder, TranscribeOptions, WaveFormat};
+use std::time::{Duration, Instant};
+
+/// 100 毫秒的 16 kHz 16 位单声道静音
+const CHUNK: [u8; 3200] = [0; 3200];
+
+fn builder(server: &MockServer) -> SessionBuilder {
+    SessionBuilder::new("en-US").endpoint(server.url().as_str())
+}
+
+fn header<'a>(headers: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+}
+
+#[test]
+fn events_arrive_in_order() {
+    let server = MockServer::start(vec![
+        MockStep::turn_start(0),
+        MockStep::hypothesis(CHUNK.len(), "hello", 0, 5_000_000),
+        MockStep::phrase(2 * CHUNK.len(), "Hello world.", 0, 10_000_000),
+        MockStep::turn_end(2 * CHUNK.len()),
+    ])
+    .unwrap();
+    let mut session = builder(&server).connect().unwrap();
+    session.write(CHUNK).unwrap();
+    session.write(CHUNK).unwrap();
+    session.end_audio().unwrap();
+
+    let mut events = Vec::new();
+    loop {
+        let event = session.recv_message().unwrap();
+        let end = event == RecognitionEvent::TurnEnd;
+        events.push(event);
+        if end {
+            break;
+        }
+    }
+    assert!(matches!(events[0], RecognitionEvent::TurnStart(_)));
+    match &events[1] {
+        RecognitionEvent::Hypothesis(hypothesis) => assert_eq!(hypothesis.text, "hello"),
+        event => panic!("unexpected event {:?}", event),
+    }
+    match &events[2] {
+        RecognitionEvent::Phrase(phrase) => {
+            assert_eq!(phrase.recognition_status, RecognitionStatus::Success);
+            assert_eq!(phrase.display_text, "Hello world.");
+            assert_eq!(phrase.duration, 10_000_000);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    assert_eq!(events.len(), 4);
+    assert!(session.is_closed());
+    assert!(matches!(session.write(CHUNK), Err(Error::Closed)));
+}
+
+#[test]
+fn policy_violation_is_forbidden() {
+    let server = MockServer::start(vec![MockStep::close(CHUNK.len(), 1008, "invalid key")]).unwrap();
+    let mut session = builder(&server).connect().unwrap();
+    session.write(CHUNK).unwrap();
+    session.flush().unwrap();
+    match session.recv_message() {
+        Err(Error::Service { code, reason, cancellation }) => {
+            assert_eq!(code, 1008);
+            assert_eq!(reason, "invalid key");
+            assert_eq!(cancellation, CancellationReason::Forbidden);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn messages_are_sent_in_protocol_order() {
+    let server = MockServer::start(vec![MockStep::turn_end(0)]).unwrap();
+    let mut session = builder(&server).audio_format(AudioFormat::TARGET).connect().unwrap();
+    let request_id = session.request_id().to_owned();
+    session.write(CHUNK).unwrap();
+    session.write(CHUNK).unwrap();
+    session.end_audio().unwrap();
+    assert_eq!(session.recv_message().unwrap(), RecognitionEvent::TurnEnd);
+
+    let connections = server.connections();
+    assert_eq!(connections.len(), 1);
+    let connection = &connections[0];
+    assert!(connection.errors.is_empty(), "{:?}", connection.errors);
+    let paths: Vec<&str> = connection.messages.iter().map(|message| message.path.as_str()).collect();
+    assert_eq!(paths[..2], ["speech.config", "speech.context"]);
+    assert!(paths.len() > 3);
+    assert!(paths[2..].iter().all(|&path| path == "audio"));
+    for message in &connection.messages[1..] {
+        assert_eq!(header(&message.headers, "X-RequestId"), Some(request_id.as_str()));
+    }
+
+    // 只有第一条 audio 消息带 Content-Type 和 WAV 头，最后一条空消息表示音频结束
+    let audio = &connection.messages[2..];
+    assert!(header(&audio[0].headers, "Content-Type").is_some());
+    assert!(audio[0].body.starts_with(b"RIFF"));
+    assert!(audio[1..].iter().all(|message| header(&message.headers, "Content-Type").is_none()));
+    assert!(audio.last().unwrap().body.is_empty());
+    let audio_len: usize = audio.iter().map(|message| message.body.len()).sum();
+    assert_eq!(audio_len, 44 + 2 * CHUNK.len());
+}
+
+#[test]
+fn continuous_mode_starts_a_new_turn() {
+    let server = MockServer::start(vec![
+        MockStep::phrase(CHUNK.len(), "one", 0, 1_000_000),
+        MockStep::turn_end(CHUNK.len()),
+        MockStep::phrase(3 * CHUNK.len(), "two", 0, 2_000_000),
+        MockStep::turn_end(3 * CHUNK.len()),
+    ])
+    .unwrap();
+    let mut session = builder(&server).recognition_mode(RecognitionMode::Conversation).connect().unwrap();
+    let first_request_id = session.request_id().to_owned();
+    session.write(CHUNK).unwrap();
+    session.flush().unwrap();
+    assert!(matches!(session.recv_message().unwrap(), RecognitionEvent::Phrase(_)));
+    assert_eq!(session.recv_message().unwrap(), RecognitionEvent::TurnEnd);
+    assert!(!session.is_closed());
+    assert_ne!(session.request_id(), first_request_id);
+    assert_eq!(session.audio_bytes_before_turn(), CHUNK.len() as u64);
+
+    session.write(CHUNK).unwrap();
+    session.write(CHUNK).unwrap();
+    session.end_audio().unwrap();
+    match session.recv_message().unwrap() {
+        RecognitionEvent::Phrase(phrase) => assert_eq!(phrase.display_text, "two"),
+        event => panic!("unexpected event {:?}", event),
+    }
+    // 第二轮覆盖了所有的音频，不会再开始新的一轮
+    assert_eq!(session.recv_message().unwrap(), RecognitionEvent::TurnEnd);
+    assert!(session.is_closed());
+
+    let connections = server.connections();
+    let contexts = connections[0].messages.iter().filter(|message| message.path == "speech.context").count();
+    assert_eq!(contexts, 2);
+}
+
+#[test]
+fn transcribe_reports_failed_recognition() {
+    let server = MockServer::start(vec![MockStep::send(0, "speech.phrase", r#"{"RecognitionStatus":"Error","Offset":0,"Duration":0}"#)]).unwrap();
+    let options = TranscribeOptions::from(builder(&server)).speed(0.0);
+    let mut wav = WaveFormat::pcm(16000, 1, 16).header(Some(CHUNK.len() as u32));
+    wav.extend_from_slice(&CHUNK);
+    match bing_stt::transcribe(&wav[..], &options) {
+        Err(Error::Recognition { status }) => assert_eq!(status, RecognitionStatus::Error),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn transcribe_times_out_without_turn_end() {
+    // 服务端收到音频之后什么都不返回
+    let server = MockServer::start(vec![MockStep::turn_start(0)]).unwrap();
+    let options = TranscribeOptions::from(builder(&server)).speed(0.0).end_of_stream_timeout(Duration::from_millis(200));
+    let mut wav = WaveFormat::pcm(16000, 1, 16).header(Some(CHUNK.len() as u32));
+    wav.extend_from_slice(&CHUNK);
+    let start = Instant::now();
+    assert!(matches!(bing_stt::transcribe(&wav[..], &options), Err(Error::Timeout)));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn transcribe_rejects_zero_sample_rate_or_channels() {
+    let server = MockServer::start(vec![MockStep::turn_end(0)]).unwrap();
+    let options = TranscribeOptions::from(builder(&server));
+    for format in [AudioFormat::new(0, 1, SampleType::I16), AudioFormat::new(16000, 0, SampleType::I16)] {
+        let result = bing_stt::transcribe_stream(&CHUNK[..], format, &options, |_| {});
+        assert!(matches!(result, Err(Error::InvalidWav(_))));
+    }
+    assert!(server.connections().is_empty());
+}
+
+/// 不限速时所有音频在第一个 turn.end 之前就发送完了，服务端结束第一轮时忽略了之后的音频，
+/// 这些音频要在第二轮中重新发送
+#[test]
+fn audio_after_turn_boundary_is_replayed() {
+    const SECOND: usize = 32000;
+    let server = MockServer::start(vec![
+        MockStep::phrase(SECOND, "Hello.", 0, 10_000_000),
+        MockStep::turn_end(SECOND),
+        MockStep::phrase(SECOND + SECOND / 2, "World.", 0, 10_000_000),
+        MockStep::turn_end(SECOND + SECOND / 2),
+    ])
+    .unwrap();
+    let options = TranscribeOptions::from(builder(&server)).speed(0.0);
+    let mut wav = WaveFormat::pcm(16000, 1, 16).header(Some(2 * SECOND as u32));
+    wav.resize(wav.len() + 2 * SECOND, 0);
+    let transcript = bing_stt::transcribe(&wav[..], &options).unwrap();
+    let phrases: Vec<(&str, Duration)> = transcript.phrases.iter().map(|phrase| (phrase.text.as_str(), phrase.offset)).collect();
+    assert_eq!(phrases, [("Hello.", Duration::ZERO), ("World.", Duration::from_secs(1))]);
+
+    let connections = server.connections();
+    let messages = &connections[0].messages;
+    let second_turn = messages.iter().rposition(|message| message.path == "speech.context").unwrap();
+    let replayed: usize = messages[second_turn..].iter().filter(|message| message.path == "audio").map(|message| message.body.len()).sum();
+    assert_eq!(replayed, 44 + SECOND);
+    assert!(messages[second_turn..].last().unwrap().body.is_empty());
+}
+
+#[test]
+fn resilient_session_replays_audio_after_disconnect() {
+    let server = MockServer::start(vec![MockStep::disconnect(2 * CHUNK.len())]).unwrap();
+    // 只保留最后 50 毫秒，重放的音频不会再次触发断开
+    let policy = ReconnectPolicy::default().initial_backoff(Duration::from_millis(10)).replay_duration(Duration::from_millis(50));
+    let mut session = ResilientSession::connect(builder(&server), policy).unwrap();
+    session.write(CHUNK).unwrap();
+    session.write(CHUNK).unwrap();
+    session.flush().unwrap();
+    for _ in 0..50 {
+        if session.reconnect_count() > 0 {
+            break;
+        }
+        assert_eq!(session.recv_message_timeout(Duration::from_millis(100)).unwrap(), None);
+    }
+    assert_eq!(session.reconnect_count(), 1);
+
+    let audio_len = |connection: &MockConnection| connection.messages.iter().filter(|message| message.path == "audio").map(|message| message.body.len()).sum::<usize>();
+    let mut connections = server.connections();
+    for _ in 0..50 {
+        if connections.len() == 2 && audio_len(&connections[1]) == CHUNK.len() / 2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        connections = server.connections();
+    }
+    assert_eq!(audio_len(&connections[0]), 2 * CHUNK.len());
+    assert_eq!(audio_len(&connections[1]), CHUNK.len() / 2);
+}
+
+/// 写入的音频的格式由 wave_header 决定，重连之后结果的 Offset 和重新发送的音频都按这个格式换算
+#[test]
+fn resilient_session_rebases_offsets_after_reconnect() {
+    let format = WaveFormat::pcm(48000, 2, 16);
+    let header = format.header(None);
+    // 100 毫秒
+    let chunk = vec![0u8; 19200];
+    let server = MockServer::start(vec![MockStep::phrase(chunk.len(), "one", 0, 1_000_000), MockStep::disconnect(2 * chunk.len())]).unwrap();
+    let policy = ReconnectPolicy::default().initial_backoff(Duration::from_millis(10));
+    let mut session = ResilientSession::connect(builder(&server).wave_header(header.clone()), policy).unwrap();
+    let next_phrase = |session: &mut ResilientSession| loop {
+        if let RecognitionEvent::Phrase(phrase) = session.recv_message().unwrap() {
+            return phrase;
+        }
+    };
+    session.write(&chunk).unwrap();
+    session.flush().unwrap();
+    assert_eq!(next_phrase(&mut session).offset, 0);
+
+    // 第一个连接收到第二段音频之后断开，重连之后只重新发送第二段，第二个连接的结果从 100 毫秒开始
+    session.write(&chunk).unwrap();
+    let _ = session.flush();
+    assert_eq!(next_phrase(&mut session).offset, 1_000_000);
+    assert_eq!(session.reconnect_count(), 1);
+
+    let connections = server.connections();
+    let audio = connections[1].messages.iter().filter(|message| message.path == "audio");
+    assert_eq!(audio.map(|message| message.body.len()).sum::<usize>(), header.len() + chunk.len());
+}
+
+#[test]
+fn disconnect_without_close_frame_closes_session() {
+    let server = MockServer::start(vec![MockStep::disconnect(CHUNK.len())]).unwrap();
+    let mut session = builder(&server).connect().unwrap();
+    session.write(CHUNK).unwrap();
+    session.flush().unwrap();
+    assert!(matches!(session.recv_message(), Err(Error::Closed)));
+    assert!(session.is_closed());
+}
+
+/// Ping 不是识别事件，recv_message_timeout 要一直等到超时
+#[test]
+fn pings_do_not_end_timeout_early() {
+    let mut server = websocket::sync::Server::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/", server.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let mut client = server.accept().ok().unwrap().accept().ok().unwrap();
+        for _ in 0..10 {
+            client.send_message(&websocket::Message::ping(b"ping".to_vec())).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        client
+    });
+    let mut session = SessionBuilder::new("en-US").endpoint(url.as_str()).connect().unwrap();
+    let start = Instant::now();
+    assert_eq!(session.recv_message_timeout(Duration::from_millis(300)).unwrap(), None);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert!(!session.is_closed());
+    drop(handle.join().unwrap());
+}